@@ -0,0 +1,166 @@
+use subdivision;
+use pattern::SamplePattern;
+use perturbation::{References, ReferenceOrbit};
+use fractal::Fractal;
+use {batch_escape, calc_sample, Sample, Samples, SetDefinition};
+
+/// The thresholds deciding which pixels are supersampled when sampling adaptively.
+///
+/// Each pixel is first calculated with a single sample at its centre. A pixel is supersampled
+/// if it contrasts with any of its eight neighbours, which is when one of them is in the set
+/// and the other isn't, or when the difference between their values reaches a threshold. The
+/// supersampled pixels have the maximum number of samples given by the set's oversampling,
+/// positioned using the set's sample pattern. If the pattern is the regular grid the samples
+/// are jittered instead, because the single samples are at the centres of the pixels rather
+/// than on the grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    /// The difference in escape iterations at which neighbouring pixels contrast.
+    pub iterations: u32,
+    /// The difference in the lighting and field line factor at which neighbouring pixels
+    /// contrast. Only used if the set is shaded.
+    pub shade: f64,
+}
+
+impl AdaptiveSampling {
+    /// Parses the thresholds from a string containing the escape iterations and shade
+    /// thresholds separated by a comma, for example `2,0.05`.
+    pub fn parse(value: &str) -> Option<AdaptiveSampling> {
+        let mut parts = value.split(',').map(|part| part.trim());
+        let iterations = parts.next()?.parse().ok()?;
+        let shade = parts.next()?.parse().ok()?;
+
+        match parts.next() {
+            None => Some(AdaptiveSampling { iterations, shade }),
+            Some(_) => None,
+        }
+    }
+
+    /// Returns true if the difference between the samples of two neighbouring pixels means
+    /// they should be supersampled.
+    fn contrasting(&self, a: &Sample, b: &Sample) -> bool {
+        if (a.iterations == 0) != (b.iterations == 0) {
+            return true;
+        }
+        a.iterations.abs_diff(b.iterations) >= self.iterations || (a.shade - b.shade).abs() >= self.shade
+    }
+}
+
+/// Calculates the samples of the set defined by `set_def` for `fractal` and adds them to
+/// `samples`, only supersampling the pixels which contrast with their neighbours. The samples
+/// are calculated relative to `reference` if it isn't `None`.
+///
+/// The samples of the pixels which aren't supersampled are copies of the sample at the centre
+/// of the pixel, so the samples have the same layout as if every pixel was supersampled.
+pub fn calc_samples<F, S>(set_def: &SetDefinition, fractal: &F, adaptive: &AdaptiveSampling, reference: Option<&ReferenceOrbit>, samples: &mut S)
+    where F: Fractal, S: Samples {
+    let (width, height) = (set_def.width_px as usize, set_def.height_px as usize);
+    let os = set_def.oversampling as usize;
+    // the centres include a border one pixel wide so the pixels on the edges of the set can be
+    // compared with their neighbours, which gives the same results however the set is split
+    let outer_width = width + 2;
+    let centre = |x: usize, y: usize| set_def.px_offset(x as f64 - 0.5, y as f64 - 0.5);
+    let mut references = reference.map(References::new);
+    let (iterations, periods, sources) = subdivision::subdivide(outer_width, height + 2, set_def.subdivision, centre, |offsets, iterations, periods| {
+        batch_escape(set_def, fractal, references.as_mut(), offsets, iterations, periods)
+    });
+    let mut centres = Vec::with_capacity(iterations.len());
+    let copy_filled = set_def.f64_colouring().is_none();
+
+    for (idx, (&iters, &period)) in iterations.iter().zip(&periods).enumerate() {
+        // the centres filled by subdivision are copies of the centres they were filled from,
+        // unless their colourings vary within the filled rectangle
+        let sample = match sources[idx] {
+            source if source != idx && copy_filled => centres[source],
+            _ => calc_sample(set_def, fractal, set_def.origin + centre(idx % outer_width, idx / outer_width).to_complex(), iters, period),
+        };
+        centres.push(sample);
+    }
+
+    let sample_pattern = match set_def.pattern {
+        SamplePattern::Grid => SamplePattern::Jittered,
+        sample_pattern => sample_pattern,
+    };
+    // find the pixels to supersample and the offsets of their samples from the origin
+    let mut refined = vec![None; width * height];
+    let mut offsets = Vec::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            let sample = &centres[(y + 1) * outer_width + x + 1];
+            let contrasting = (y..y + 3)
+                .flat_map(|ny| (x..x + 3).map(move |nx| ny * outer_width + nx))
+                .any(|idx| adaptive.contrasting(sample, &centres[idx]));
+
+            if contrasting && os > 1 {
+                refined[y * width + x] = Some(offsets.len());
+                let pixel = set_def.pixel_index(x, y);
+
+                for slot in 0..os * os {
+                    let (dx, dy) = sample_pattern.position(pixel, slot, os, set_def.seed);
+                    offsets.push(set_def.pixel_offset(x, y) + set_def.px_offset(dx, dy));
+                }
+            }
+        }
+    }
+    let mut iterations = vec![0; offsets.len()];
+    let mut periods = vec![0; offsets.len()];
+    batch_escape(set_def, fractal, references.as_mut(), &offsets, &mut iterations, &mut periods);
+    let supersamples = offsets.iter().zip(iterations.iter().zip(&periods))
+        .map(|(&offset, (&iters, &period))| calc_sample(set_def, fractal, set_def.origin + offset.to_complex(), iters, period))
+        .collect::<Vec<_>>();
+    debug!("supersampled {} of {} pixels", supersamples.len() / (os * os), width * height);
+
+    for row in 0..height * os {
+        for col in 0..width * os {
+            let (x, y) = (col / os, row / os);
+            let sample = match refined[y * width + x] {
+                Some(start) => supersamples[start + (row % os) * os + col % os],
+                None => centres[(y + 1) * outer_width + x + 1],
+            };
+            samples.push(sample);
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fractal::Mandelbrot;
+    use SetData;
+
+    fn calc_set(set_def: &SetDefinition, adaptive: &AdaptiveSampling) -> SetData {
+        let mut set_data = SetData::new(set_def, set_def.sample_count());
+        calc_samples(set_def, &Mandelbrot, adaptive, None, &mut set_data);
+        set_data
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(AdaptiveSampling::parse("2, 0.05"), Some(AdaptiveSampling { iterations: 2, shade: 0.05 }));
+        assert_eq!(AdaptiveSampling::parse("2"), None);
+        assert_eq!(AdaptiveSampling::parse("2,0.05,1"), None);
+        assert_eq!(AdaptiveSampling::parse("two,0.05"), None);
+    }
+
+    #[test]
+    fn supersamples_contrasting_pixels() {
+        let adaptive = AdaptiveSampling { iterations: 2, shade: 0.1 };
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 60, 4, 100, 2.0);
+        let set_data = calc_set(&def, &adaptive);
+        assert_eq!(set_data.data.len(), def.sample_count());
+        assert_eq!(calc_set(&def, &adaptive).data, set_data.data);
+
+        let pixel_samples = |x: u32, y: u32| {
+            (0..16).map(|slot| set_data.data[((y * 4 + slot / 4) * 240 + x * 4 + slot % 4) as usize]).collect::<Vec<_>>()
+        };
+        // a pixel far from the set and a pixel inside the main cardioid aren't supersampled
+        assert!(pixel_samples(2, 2).windows(2).all(|pair| pair[0] == pair[1]));
+        assert!(pixel_samples(38, 20).iter().all(|&iters| iters == 0));
+        // the pixels crossing the boundary of the cardioid on the real axis are supersampled
+        let boundary = (40..60).map(|x| pixel_samples(x, 20)).filter(|samples| samples.contains(&0));
+        assert!(boundary.into_iter().any(|samples| samples.iter().any(|&iters| iters != 0)));
+    }
+}
@@ -0,0 +1,157 @@
+use complex::Complex;
+use fractal::Fractal;
+use std::f64::consts::PI;
+
+/// The frequency of the stripes in the stripe average colouring.
+const STRIPE_DENSITY: f64 = 5.0;
+
+/// The algorithms that colour a point by averaging a function over its orbit.
+///
+/// The average is interpolated between the average including the final orbit point and the
+/// average excluding it, weighted by the fractional part of the smooth escape count. This
+/// makes the colouring continuous across iteration bands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Average {
+    /// The average of 0.5 + 0.5 sin(d arg z) where d is the stripe density.
+    Stripe,
+    /// The average of the position of |z| between the bounds given by the triangle inequality,
+    /// | |z²| - |c| | <= |z² + c| <= |z²| + |c|.
+    TriangleInequality,
+    /// The average of the angle between successive steps of the orbit.
+    Curvature,
+}
+
+impl Average {
+    /// Returns the term added to the average for the orbit point `z` given the two previous
+    /// points, or `None` if there is no term for the point.
+    fn term(&self, z: Complex, prev: Option<Complex>, prev2: Option<Complex>, c: Complex) -> Option<f64> {
+        match *self {
+            Average::Stripe => Some(0.5 + 0.5 * (STRIPE_DENSITY * z.arg()).sin()),
+            Average::TriangleInequality => {
+                let prev_sqr = prev?.norm_sqr();
+                let min = (prev_sqr - c.abs()).abs();
+                let max = prev_sqr + c.abs();
+
+                if max > min {
+                    Some((z.abs() - min) / (max - min))
+                } else {
+                    None
+                }
+            }
+            Average::Curvature => {
+                let (prev, prev2) = (prev?, prev2?);
+                let step = prev - prev2;
+
+                if step.norm_sqr() > 0.0 {
+                    Some(((z - prev) / step).arg().abs() / PI)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Returns the smoothly interpolated average for `point` which is assumed to be outside the
+/// set of `fractal`, or zero if it doesn't escape. The value is in the range 0 to 1.
+///
+/// Large escape radii give smoother results.
+pub fn average_value<F: Fractal>(fractal: &F, point: Complex, average: Average, max_iterations: u32, escape_radius: f64) -> f64 {
+    let escape_value = escape_radius * escape_radius;
+    let mut z = fractal.init(point);
+    let mut prev = None;
+    let mut prev2 = None;
+    let mut sum = 0.0;
+    let mut count = 0;
+    // the term for the current value of z, excluded from the average before interpolation
+    let mut last_term = 0.0;
+
+    let constant = fractal.constant(point);
+
+    if let Some(term) = average.term(z, prev, prev2, constant) {
+        sum = term;
+        last_term = term;
+        count = 1;
+    }
+    for _ in 0..max_iterations {
+        let norm_sqr = z.norm_sqr();
+
+        if norm_sqr > escape_value {
+            if count == 0 {
+                return 0.0;
+            }
+            let avg = sum / count as f64;
+            let prev_avg = if count > 1 { (sum - last_term) / (count - 1) as f64 } else { avg };
+            // the fractional part of the smooth escape count, 1 when |z| is just over the
+            // escape radius and 0 when it's the escape radius squared
+            let frac = (1.0 + (escape_radius.ln() / (0.5 * norm_sqr.ln())).log2()).clamp(0.0, 1.0);
+            return frac * avg + (1.0 - frac) * prev_avg;
+        }
+        prev2 = prev;
+        prev = Some(z);
+        z = fractal.step(z, point);
+
+        if let Some(term) = average.term(z, prev, prev2, constant) {
+            sum += term;
+            last_term = term;
+            count += 1;
+        } else {
+            last_term = 0.0;
+        }
+    }
+    0.0
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fractal::Mandelbrot;
+    use precision;
+
+    /// Checks the average is continuous where the escape iterations change, by finding the
+    /// boundaries between iteration bands along a line and comparing the values either side.
+    fn assert_continuous(average: Average) {
+        let iterations = |real: f64| precision::escape_iterations(Complex::new(real, 0.6), 1000, 1000.0, None).0;
+        let value = |real: f64| average_value(&Mandelbrot, Complex::new(real, 0.6), average, 1000, 1000.0);
+        let mut boundaries = 0;
+
+        for i in 0..200 {
+            let (mut low, mut high) = (0.3 + i as f64 * 1e-4, 0.3 + (i + 1) as f64 * 1e-4);
+
+            if iterations(low) == iterations(high) {
+                continue;
+            }
+            while high - low > 1e-13 {
+                let mid = (low + high) / 2.0;
+
+                if iterations(mid) == iterations(low) { low = mid; } else { high = mid; }
+            }
+            assert!((value(low) - value(high)).abs() < 1e-3, "jump from {} to {} at {}", value(low), value(high), low);
+            assert!(value(low) >= 0.0 && value(low) <= 1.0, "value {} out of range", value(low));
+            boundaries += 1;
+        }
+        assert!(boundaries > 10);
+    }
+
+    #[test]
+    fn stripe_continuous() {
+        assert_continuous(Average::Stripe);
+    }
+
+    #[test]
+    fn triangle_inequality_continuous() {
+        assert_continuous(Average::TriangleInequality);
+    }
+
+    #[test]
+    fn curvature_continuous() {
+        assert_continuous(Average::Curvature);
+    }
+
+    #[test]
+    fn in_set_is_zero() {
+        assert_eq!(average_value(&Mandelbrot, Complex::new(0.0, 0.0), Average::Stripe, 100, 2.0), 0.0);
+    }
+}
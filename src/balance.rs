@@ -0,0 +1,176 @@
+use complex::Complex;
+use fractal::{self, Fractal};
+use {simd, SetDefinition, Tile};
+
+/// The width and height in pixels of the cells whose cost is estimated from a single sample.
+const CELL_SIZE: u32 = 8;
+/// The width and height in cells of the tiles before they are split by cost.
+const MAX_TILE_CELLS: u32 = 32;
+/// The width and height in pixels of the largest tiles.
+pub const MAX_TILE_SIZE: u32 = CELL_SIZE * MAX_TILE_CELLS;
+/// The number of tiles of average cost per thread. Tiles more expensive than the average are
+/// split into quarters until they are cheaper or a single cell.
+const TILES_PER_THREAD: u32 = 16;
+/// The estimated cost of a pixel apart from iterating its samples, in iterations.
+const PIXEL_OVERHEAD: f64 = 4.0;
+
+/// Estimates of the cost of calculating the pixels of a set, made by calculating the escape
+/// iterations of one sample per cell of pixels.
+struct CostMap {
+    cols: u32,
+    width_px: u32,
+    height_px: u32,
+    /// The estimated cost of each pixel in each cell, by row.
+    costs: Vec<f64>,
+}
+
+impl CostMap {
+    fn new<F: Fractal>(set_def: &SetDefinition, fractal: &F) -> CostMap {
+        let cols = set_def.width_px.div_ceil(CELL_SIZE);
+        let rows = set_def.height_px.div_ceil(CELL_SIZE);
+        let mut costs = Vec::with_capacity((cols * rows) as usize);
+        let mut iterations = vec![0; cols as usize];
+        // the costs are only estimates, so the cells are sampled in f64 at any zoom
+        let px_size = set_def.px_size.to_f64();
+        let tolerance = if set_def.periodicity { Some(set_def.periodicity_tolerance()) } else { None };
+
+        for row in 0..rows {
+            // sample the middle of each cell, or of the part of it inside the set
+            let centre = |px: u32, size: u32| (px * CELL_SIZE) as f64 + (CELL_SIZE.min(size - px * CELL_SIZE) as f64) / 2.0;
+            let imag = centre(row, set_def.height_px) * px_size;
+            let points = (0..cols)
+                .map(|col| set_def.origin + Complex::new(centre(col, set_def.width_px) * px_size, imag))
+                .collect::<Vec<_>>();
+            if fractal.is_mandelbrot() {
+                simd::escape_iterations_row(&points, set_def.max_iterations, set_def.escape_radius, &mut iterations);
+            } else {
+                for (point, iters) in points.iter().zip(iterations.iter_mut()) {
+                    *iters = fractal::escape_iterations(fractal, *point, set_def.max_iterations, set_def.escape_radius, None).0;
+                }
+            }
+
+            for (point, &iters) in points.iter().zip(&iterations) {
+                // the points that don't escape are iterated until a cycle is found if the set
+                // checks for periodicity
+                let iters = match iters {
+                    0 if set_def.interior_check && fractal.in_known_interior(*point) => 0,
+                    0 if tolerance.is_some() => {
+                        fractal::escape_iterations_counted(fractal, *point, set_def.max_iterations, set_def.escape_radius, tolerance).2
+                    }
+                    0 => set_def.max_iterations,
+                    iters => iters,
+                };
+                let samples = (set_def.oversampling * set_def.oversampling) as f64;
+                costs.push(iters as f64 * samples + PIXEL_OVERHEAD);
+            }
+        }
+        CostMap { cols, width_px: set_def.width_px, height_px: set_def.height_px, costs }
+    }
+
+    /// Returns the estimated cost of the pixels in a rectangle whose edges are on the edges of
+    /// the cells or the set.
+    fn cost(&self, x_px: u32, y_px: u32, width_px: u32, height_px: u32) -> f64 {
+        let mut cost = 0.0;
+
+        for row in y_px / CELL_SIZE..(y_px + height_px).div_ceil(CELL_SIZE) {
+            let cell_height = CELL_SIZE.min(self.height_px - row * CELL_SIZE);
+
+            for col in x_px / CELL_SIZE..(x_px + width_px).div_ceil(CELL_SIZE) {
+                let cell_width = CELL_SIZE.min(self.width_px - col * CELL_SIZE);
+                cost += self.costs[(row * self.cols + col) as usize] * (cell_width * cell_height) as f64;
+            }
+        }
+        cost
+    }
+}
+
+/// Splits a set definition into tiles of roughly even estimated cost for `fractal`, most
+/// expensive first.
+///
+/// The cost of each region of the set is estimated with a quick low resolution pass. Large
+/// tiles are split into quarters until their estimated cost is below the average tile cost
+/// needed to give each thread several tiles. Handing out the most expensive tiles first means
+/// the threads finish at about the same time, without one thread still working on a large
+/// expensive tile after the others have run out.
+pub fn balanced_tiles<F: Fractal>(set_def: &SetDefinition, fractal: &F, threads: u32) -> Vec<Tile> {
+    let costs = CostMap::new(set_def, fractal);
+    let max_cost = costs.cost(0, 0, set_def.width_px, set_def.height_px) / (threads * TILES_PER_THREAD) as f64;
+    let mut tiles = Vec::new();
+    let mut pending = set_def.tiles(MAX_TILE_SIZE)
+        .into_iter()
+        .map(|tile| (tile.x_px, tile.y_px, tile.def.width_px, tile.def.height_px))
+        .collect::<Vec<_>>();
+
+    while let Some((x_px, y_px, width_px, height_px)) = pending.pop() {
+        let cost = costs.cost(x_px, y_px, width_px, height_px);
+
+        if cost <= max_cost || (width_px <= CELL_SIZE && height_px <= CELL_SIZE) {
+            tiles.push((cost, set_def.tile(x_px, y_px, width_px, height_px)));
+            continue;
+        }
+        // split on the edges of the cells, which may leave only one column or row of cells
+        let left = (width_px.div_ceil(CELL_SIZE) / 2 * CELL_SIZE).min(width_px);
+        let bottom = (height_px.div_ceil(CELL_SIZE) / 2 * CELL_SIZE).min(height_px);
+
+        for &(x, w) in &[(x_px, left), (x_px + left, width_px - left)] {
+            for &(y, h) in &[(y_px, bottom), (y_px + bottom, height_px - bottom)] {
+                if w > 0 && h > 0 {
+                    pending.push((x, y, w, h));
+                }
+            }
+        }
+    }
+    tiles.sort_by(|a, b| b.0.total_cmp(&a.0));
+    tiles.into_iter().map(|(_, tile)| tile).collect()
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fractal::Mandelbrot;
+
+    #[test]
+    fn tiles_cover_set() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 300, 2, 500, 2.0);
+        let tiles = balanced_tiles(&def, &Mandelbrot, 4);
+        let mut covered = vec![0; (def.width_px * def.height_px) as usize];
+
+        for tile in &tiles {
+            for y in tile.y_px..tile.y_px + tile.def.height_px {
+                for x in tile.x_px..tile.x_px + tile.def.width_px {
+                    covered[(y * def.width_px + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn expensive_tiles_first() {
+        // the boundary of the set crosses the view so some areas are much more expensive
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 600, 2, 500, 2.0);
+        let costs = CostMap::new(&def, &Mandelbrot);
+        let tiles = balanced_tiles(&def, &Mandelbrot, 8);
+        let tile_costs = tiles.iter()
+            .map(|tile| costs.cost(tile.x_px, tile.y_px, tile.def.width_px, tile.def.height_px))
+            .collect::<Vec<_>>();
+        assert!(tile_costs.windows(2).all(|pair| pair[0] >= pair[1]));
+        // the expensive areas are split into smaller tiles than the cheap areas
+        let max_cost = tile_costs.iter().sum::<f64>() / (8 * TILES_PER_THREAD) as f64;
+        let size = |tile: &Tile| tile.def.width_px * tile.def.height_px;
+        assert!(tiles.iter().zip(&tile_costs).all(|(tile, &cost)| cost <= max_cost || size(tile) == CELL_SIZE * CELL_SIZE));
+        assert!(tiles.iter().any(|tile| size(tile) == CELL_SIZE * CELL_SIZE));
+        assert!(tiles.iter().any(|tile| size(tile) >= 16 * CELL_SIZE * CELL_SIZE));
+    }
+
+    #[test]
+    fn periodic_interior_cost() {
+        // a cell in the period-3 bulb, where the orbits are found to be cycles long before the
+        // maximum iterations
+        let def = SetDefinition::new(-0.13, -0.11, 0.74, 0.76, 8, 1, 5000, 2.0);
+        assert!(CostMap::new(&def, &Mandelbrot).costs[0] < 1000.0);
+        assert_eq!(CostMap::new(&def.with_periodicity(false), &Mandelbrot).costs[0], 5000.0 + PIXEL_OVERHEAD);
+    }
+}
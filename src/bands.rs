@@ -0,0 +1,259 @@
+use bmp::Image;
+use png;
+use tiff::encoder::{colortype, TiffEncoder, TiffKind, TiffKindBig, TiffKindStandard};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use balance;
+use fractal::Fractal;
+use stream::{self, PixelData};
+use {calc_tiles_parallel, reference_orbit, SetDefinition, TILE_SIZE};
+
+/// The estimated memory used by each sample of the tiles being calculated, in bytes. This
+/// includes the working storage used while calculating the samples as well as their values.
+const SAMPLE_BYTES: u64 = 64;
+/// The memory used by each pixel of a band in bytes, for its colour and for the bytes passed
+/// to the encoder.
+const PIXEL_BYTES: u64 = 6;
+/// The size in bytes of the largest image written as a standard TIFF rather than a BigTIFF.
+/// Standard TIFF files use 32 bit offsets, and this leaves room for the tags.
+const TIFF_MAX_BYTES: u64 = (1 << 32) - (1 << 20);
+
+/// The format of an image file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+    Tiff,
+    /// TIFF with 64 bit offsets, for images too big for standard TIFF.
+    BigTiff,
+}
+
+impl ImageFormat {
+    /// Returns the format of an image file from the extension of its path, or BMP if the
+    /// extension isn't recognised. TIFF images too big for standard TIFF are written as
+    /// BigTIFF.
+    pub fn from_path(path: &str, width_px: u32, height_px: u32) -> ImageFormat {
+        let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+
+        match extension.as_str() {
+            "png" => ImageFormat::Png,
+            "tif" | "tiff" if width_px as u64 * height_px as u64 * 3 > TIFF_MAX_BYTES => ImageFormat::BigTiff,
+            "tif" | "tiff" => ImageFormat::Tiff,
+            _ => ImageFormat::Bmp,
+        }
+    }
+}
+
+/// How a set is rendered in bands to keep the memory used below a limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BandPlan {
+    /// The number of rows of pixels in each band, apart from the last band which can have fewer.
+    rows: u32,
+    /// The width and height in pixels of the squares which have one sample in the preview used
+    /// to find the colouring.
+    preview_scale: u32,
+}
+
+impl BandPlan {
+    /// Returns the largest bands of `set_def` which can be calculated by `threads` threads in
+    /// `memory_limit` bytes, or an error if the limit is too small for a band of one row.
+    fn new(set_def: &SetDefinition, threads: u32, memory_limit: u64) -> Result<BandPlan, String> {
+        let tile_size = if set_def.cost_estimate { balance::MAX_TILE_SIZE } else { TILE_SIZE } as u64;
+        let tile_samples = tile_size * tile_size * (set_def.oversampling * set_def.oversampling) as u64;
+        let tiles_bytes = threads as u64 * tile_samples * SAMPLE_BYTES;
+        let row_bytes = set_def.width_px as u64 * PIXEL_BYTES;
+
+        if tiles_bytes + row_bytes > memory_limit {
+            return Err(format!("The memory limit of {} bytes is too small, at least {} bytes are needed",
+                memory_limit, tiles_bytes + row_bytes));
+        }
+        let rows = ((memory_limit - tiles_bytes) / row_bytes).min(set_def.height_px as u64) as u32;
+        // the preview is calculated before the bands so it can use all the memory. When it's
+        // mirrored the calculated rows are held alongside the whole mirrored preview, which can
+        // take twice the memory
+        let preview_pixels = |scale: u32| set_def.width_px.div_ceil(scale) as u64 * set_def.height_px.div_ceil(scale) as u64;
+        let preview_sample_bytes = if set_def.symmetry { 2 * SAMPLE_BYTES } else { SAMPLE_BYTES };
+        let mut preview_scale = 1;
+
+        while preview_pixels(preview_scale) * preview_sample_bytes > memory_limit && preview_pixels(preview_scale) > 1 {
+            preview_scale += 1;
+        }
+        Ok(BandPlan { rows, preview_scale })
+    }
+}
+
+/// Calculates the set defined by `set_def` for `fractal` in bands of rows, writing each band to
+/// the image file at `path` as soon as it's calculated so the whole image is never in memory.
+/// The memory used is kept below `memory_limit` bytes whatever the size of the image.
+///
+/// The colouring is found from a preview of the whole set before the bands are calculated.
+/// The preview has fewer samples than pixels if the image is too big for the memory limit.
+pub fn render_bands<F: Fractal>(
+    set_def: &SetDefinition,
+    fractal: &F,
+    threads: u32,
+    texture: Option<Image>,
+    path: &str,
+    format: ImageFormat,
+    memory_limit: u64,
+) -> Result<(), String> {
+    let set_def = &set_def.with_resolved_precision();
+    let plan = BandPlan::new(set_def, threads, memory_limit)?;
+    info!("rendering bands of {} rows with a preview scale of {}", plan.rows, plan.preview_scale);
+    let colouring = Arc::new(stream::preview_colouring(set_def, fractal, threads, texture, plan.preview_scale));
+    let band_count = set_def.height_px.div_ceil(plan.rows);
+    // the reference orbit of the whole set is shared by all the bands
+    let reference = reference_orbit(set_def, fractal);
+    let band = |idx: u32| {
+        // the bands are written from the top of the image, which is the top of the set
+        let top_px = set_def.height_px - idx * plan.rows;
+        let rows = plan.rows.min(top_px);
+        let band = set_def.tile(0, top_px - rows, set_def.width_px, rows);
+        debug!("calculating band {} of {}", idx + 1, band_count);
+        calc_tiles_parallel(&band.def, fractal, reference.as_ref(), threads, PixelData::new(&band.def, colouring.clone())).rgb_rows()
+    };
+    write_image(path, format, set_def.width_px, set_def.height_px, plan.rows, band)
+}
+
+/// Writes an image which is already in memory to the file at `path`.
+pub fn save_image(img: &Image, path: &str, format: ImageFormat) -> Result<(), String> {
+    if format == ImageFormat::Bmp {
+        return img.save(path).map_err(|err| format!("Failed to write image '{}': {}", path, err));
+    }
+    let (width, height) = (img.get_width(), img.get_height());
+    let rgb_rows = |_| {
+        let mut bytes = Vec::with_capacity((width * height * 3) as usize);
+
+        for (x, y) in img.coordinates() {
+            let pixel = img.get_pixel(x, y);
+            bytes.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+        }
+        bytes
+    };
+    write_image(path, format, width, height, height, rgb_rows)
+}
+
+/// Writes an image to the file at `path` a band of rows at a time, starting from the top.
+///
+/// `band` returns the red, green and blue bytes of the pixels in a band given its index. Every
+/// band has `band_rows` rows apart from the last, which has the rows that are left.
+fn write_image<B>(path: &str, format: ImageFormat, width: u32, height: u32, band_rows: u32, band: B) -> Result<(), String>
+    where B: FnMut(u32) -> Vec<u8> {
+    let file = File::create(path).map_err(|err| format!("Failed to create image '{}': {}", path, err))?;
+    let writer = BufWriter::new(file);
+    let band_count = height.div_ceil(band_rows);
+
+    let result = match format {
+        ImageFormat::Png => write_png(writer, width, height, band_count, band),
+        ImageFormat::Tiff => write_tiff::<TiffKindStandard, _>(writer, width, height, band_rows, band_count, band),
+        ImageFormat::BigTiff => write_tiff::<TiffKindBig, _>(writer, width, height, band_rows, band_count, band),
+        ImageFormat::Bmp => Err("BMP images can't be written in bands".into()),
+    };
+    result.map_err(|err| format!("Failed to write image '{}': {}", path, err))
+}
+
+fn write_png<B>(writer: BufWriter<File>, width: u32, height: u32, band_count: u32, mut band: B) -> Result<(), Box<dyn Error>>
+    where B: FnMut(u32) -> Vec<u8> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut stream = encoder.write_header()?.into_stream_writer()?;
+
+    for idx in 0..band_count {
+        stream.write_all(&band(idx))?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+/// Writes an uncompressed TIFF image with a strip for each band.
+fn write_tiff<K, B>(writer: BufWriter<File>, width: u32, height: u32, band_rows: u32, band_count: u32, mut band: B) -> Result<(), Box<dyn Error>>
+    where K: TiffKind, B: FnMut(u32) -> Vec<u8> {
+    let mut encoder = TiffEncoder::<_, K>::new_generic(writer)?;
+    let mut image = encoder.new_image::<colortype::RGB8>(width, height)?;
+    image.rows_per_strip(band_rows)?;
+
+    for idx in 0..band_count {
+        image.write_strip(&band(idx))?;
+    }
+    image.finish()?;
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use tiff;
+    use fractal::Mandelbrot;
+    use {calc_set, Colouring};
+
+    /// Writes the set in bands of `band_rows` rows and returns the bytes of the decoded image.
+    fn write_and_decode(set_def: &SetDefinition, colouring: &Arc<Colouring>, format: ImageFormat, band_rows: u32) -> Vec<u8> {
+        let path = env::temp_dir().join(format!("mandelbrot-bands-{}-{:?}", process::id(), format));
+        let path = path.to_str().unwrap();
+        let band = |idx: u32| {
+            let top_px = set_def.height_px - idx * band_rows;
+            let band = set_def.tile(0, top_px - band_rows.min(top_px), set_def.width_px, band_rows.min(top_px));
+            calc_tiles_parallel(&band.def, &Mandelbrot, None, 2, PixelData::new(&band.def, colouring.clone())).rgb_rows()
+        };
+        write_image(path, format, set_def.width_px, set_def.height_px, band_rows, band).unwrap();
+
+        let bytes = if format == ImageFormat::Png {
+            let mut reader = png::Decoder::new(File::open(path).unwrap()).read_info().unwrap();
+            let mut bytes = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut bytes).unwrap();
+            bytes
+        } else {
+            match tiff::decoder::Decoder::new(File::open(path).unwrap()).unwrap().read_image().unwrap() {
+                tiff::decoder::DecodingResult::U8(bytes) => bytes,
+                _ => panic!("Unexpected TIFF sample type"),
+            }
+        };
+        fs::remove_file(path).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn from_path() {
+        assert_eq!(ImageFormat::from_path("a.png", 100, 100), ImageFormat::Png);
+        assert_eq!(ImageFormat::from_path("a.TIF", 100, 100), ImageFormat::Tiff);
+        assert_eq!(ImageFormat::from_path("a.tiff", 40_000, 40_000), ImageFormat::BigTiff);
+        assert_eq!(ImageFormat::from_path("a.bmp", 100, 100), ImageFormat::Bmp);
+        assert_eq!(ImageFormat::from_path("mandelbrot", 100, 100), ImageFormat::Bmp);
+    }
+
+    #[test]
+    fn plan_within_limit() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 3000, 2, 100, 2.0);
+        let plan = BandPlan::new(&def, 4, 16 << 20).unwrap();
+        let tiles_bytes = 4 * (TILE_SIZE * TILE_SIZE * 4) as u64 * SAMPLE_BYTES;
+        assert!(plan.rows > 0 && plan.rows < def.height_px);
+        assert!(tiles_bytes + plan.rows as u64 * 3000 * PIXEL_BYTES <= 16 << 20);
+        assert!((3000 / plan.preview_scale) as u64 * (2000 / plan.preview_scale) as u64 * 2 * SAMPLE_BYTES <= 16 << 20);
+        // without mirroring the preview only needs its own samples
+        let unmirrored = BandPlan::new(&def.with_symmetry(false), 4, 16 << 20).unwrap();
+        assert!(unmirrored.preview_scale < plan.preview_scale);
+        assert!((3000 / unmirrored.preview_scale) as u64 * (2000 / unmirrored.preview_scale) as u64 * SAMPLE_BYTES <= 16 << 20);
+        assert!(BandPlan::new(&def, 4, 1 << 20).is_err());
+        // the whole set fits in one band
+        assert_eq!(BandPlan::new(&def, 4, 1 << 30).unwrap(), BandPlan { rows: 2000, preview_scale: 1 });
+    }
+
+    #[test]
+    fn bands_match_pixels() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 0.25, 96, 2, 200, 2.0);
+        let colouring = Arc::new(Colouring::new(&calc_set(&def, &Mandelbrot, None), None));
+        let expected = calc_tiles_parallel(&def, &Mandelbrot, None, 2, PixelData::new(&def, colouring.clone())).rgb_rows();
+
+        for &format in &[ImageFormat::Png, ImageFormat::Tiff, ImageFormat::BigTiff] {
+            assert!(write_and_decode(&def, &colouring, format, 7) == expected, "{:?}", format);
+        }
+    }
+}
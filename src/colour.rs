@@ -1,7 +1,7 @@
 use bmp::Pixel;
 use vector3d::Vector3d;
 
-const BLACK: Colour = Colour { r: 0, g: 0, b: 0 };
+pub const BLACK: Colour = Colour { r: 0, g: 0, b: 0 };
 
 /// A 24-bit RGB colour.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -27,24 +27,29 @@ impl Colour {
         Colour::new(red as u8, green as u8, blue as u8)
     }
 
-    pub fn to_vector3d(&self) -> Vector3d {
+    pub fn to_vector3d(self) -> Vector3d {
         Vector3d::new(self.r as f64, self.g as f64, self.b as f64)
     }
 
+    pub fn from_pixel(pixel: &Pixel) -> Colour {
+        Colour::new(pixel.r, pixel.g, pixel.b)
+    }
+
     pub fn pixel(&self) -> Pixel {
         px!(self.r, self.g, self.b)
     }
 }
 
-pub fn pixel_colour(
-    set: &Vec<u32>,
+/// Returns the colour of a pixel by averaging the colours of the samples calculated for it.
+///
+/// `sample_colour` returns the colour of the sample at an index into the set data.
+pub fn pixel_colour<F>(
     real_idx: u32,
     imag_idx: u32,
     width_px: u32,
     oversampling: u32,
-    min_iter: u32,
-    colours: &Vec<Colour>,
-) -> Colour {
+    sample_colour: &F,
+) -> Colour where F: Fn(usize) -> Colour {
 
     // index of the bottom-left pixel
     let idx_base = (width_px * imag_idx * oversampling * oversampling) + (real_idx * oversampling);
@@ -55,27 +60,25 @@ pub fn pixel_colour(
     for i in 0..oversampling {
         for r in 0..oversampling {
             let idx = idx_base + i * width_px * oversampling + r;
-            let iters = set[idx as usize];
-            // use the number of iterations to look up the colour in the palette
-            // the palette has the right number of colours so each number of iterations
-            // is rendered in a different colour
-            let col = if iters == 0 {
-                BLACK
-            } else {
-                colours[(iters - min_iter) as usize]
-            };
-            total_col = total_col + col.to_vector3d();
+            total_col = total_col + sample_colour(idx as usize).to_vector3d();
         }
     }
     let average_col = total_col / ((oversampling * oversampling) as f64);
     Colour::from_vector3d(&average_col)
 }
 
+/// Returns the colour from the palette at `fraction` of the way along it. The fraction is
+/// clamped to the range 0 to 1.
+pub fn palette_colour(colours: &[Colour], fraction: f64) -> Colour {
+    let fraction = fraction.clamp(0.0, 1.0);
+    colours[(fraction * (colours.len() - 1) as f64).round() as usize]
+}
+
 // TODO split some of this out into helper functions so it's easier to test
 /// Creates a vector of colours of the specified size defined by the colours in `colours`.
 ///
 /// The colours describe a path through the 3D cube of RGB colours.
-pub fn palette(size: u32, colours: &Vec<Colour>) -> Vec<Colour> {
+pub fn palette(size: u32, colours: &[Colour]) -> Vec<Colour> {
     if colours.len() < 2 {
         panic!("A palette is defined by two or more colours but the size was {}", size);
     }
@@ -85,7 +88,7 @@ pub fn palette(size: u32, colours: &Vec<Colour>) -> Vec<Colour> {
     // TODO this is a bad name, it's not the number of colours, it's the number of gaps between them
     let num_cols = size - 1;
     // convert the colours to Vector3d vertices defining the points in the path through the colour cube
-    let vertices = colours.iter().map(|col| Vector3d::from_colour(col)).collect::<Vec<_>>();
+    let vertices = colours.iter().map(Vector3d::from_colour).collect::<Vec<_>>();
     // relative vectors from each vertex to the next, 1 element shorter than vertices
     let rel_vecs = relative_vectors(&vertices);
     // divide the size by the number of vertices to get the number of colours per segment
@@ -131,7 +134,7 @@ pub fn palette(size: u32, colours: &Vec<Colour>) -> Vec<Colour> {
 ///
 /// The returned vector contains the relative vector from each vertex to the next vertex.
 /// Therefore it contains one element less than the input vector.
-fn relative_vectors(vertices: &Vec<Vector3d>) -> Vec<Vector3d> {
+fn relative_vectors(vertices: &[Vector3d]) -> Vec<Vector3d> {
     if vertices.len() < 2 {
         vec![]
     } else {
@@ -148,7 +151,7 @@ mod tests {
 
     #[test]
     fn relative_vectors_empty() {
-        assert!(super::relative_vectors(&vec![]).is_empty());
+        assert!(super::relative_vectors(&[]).is_empty());
     }
 
     #[test]
@@ -170,7 +173,7 @@ mod tests {
 
     #[test]
     fn palette_2_colours_on_axis() {
-        let cols = palette(6, &vec![Colour::new(0, 0, 0), Colour::new(255, 0, 0)]);
+        let cols = palette(6, &[Colour::new(0, 0, 0), Colour::new(255, 0, 0)]);
         let expected = vec![
             Colour::new(0, 0, 0),
             Colour::new(51, 0, 0),
@@ -184,7 +187,7 @@ mod tests {
 
     #[test]
     fn palette_2_colours_long_diagonal() {
-        let cols = palette(6, &vec![Colour::new(0, 0, 0), Colour::new(255, 255, 255)]);
+        let cols = palette(6, &[Colour::new(0, 0, 0), Colour::new(255, 255, 255)]);
         let expected = vec![
             Colour::new(0, 0, 0),
             Colour::new(51, 51, 51),
@@ -198,7 +201,7 @@ mod tests {
 
     #[test]
     fn palette_3_colours_along_axes() {
-        let colours = &vec![
+        let colours = &[
             Colour::new(0, 0, 0),
             Colour::new(255, 0, 0),
             Colour::new(255, 255, 0),
@@ -219,4 +222,12 @@ mod tests {
         ];
         assert_eq!(cols, expected);
     }
+
+    #[test]
+    fn palette_colour_clamped() {
+        let cols = palette(6, &[Colour::new(0, 0, 0), Colour::new(255, 0, 0)]);
+        assert_eq!(palette_colour(&cols, -1.0), Colour::new(0, 0, 0));
+        assert_eq!(palette_colour(&cols, 0.4), Colour::new(102, 0, 0));
+        assert_eq!(palette_colour(&cols, 2.0), Colour::new(255, 0, 0));
+    }
 }
@@ -1,33 +1,320 @@
-use std::ops::Mul;
-use std::ops::Add;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use precision::{Float, Real};
 
+/// A complex number whose parts are `T`, which is f64 unless another float type is given.
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Complex {
-    pub real: f64,
-    pub imag: f64,
+pub struct Complex<T = f64> {
+    pub real: T,
+    pub imag: T,
 }
 
-impl Complex {
-    pub fn new(real: f64, imag: f64) -> Complex {
+impl<T: Real> Complex<T> {
+    pub fn new(real: T, imag: T) -> Complex<T> {
         Complex { real, imag }
     }
+
+    pub fn from_complex(value: Complex) -> Complex<T> {
+        Complex::new(T::from_f64(value.real), T::from_f64(value.imag))
+    }
+
+    /// Returns the square of the magnitude. This is cheaper than `abs` because it avoids
+    /// the square root.
+    pub fn norm_sqr(&self) -> T {
+        self.real * self.real + self.imag * self.imag
+    }
+
+    /// Returns the nearest complex number with f64 parts.
+    pub fn to_complex(self) -> Complex {
+        Complex::new(self.real.to_f64(), self.imag.to_f64())
+    }
+}
+
+impl<T: Float> Complex<T> {
+    /// Returns the complex number with magnitude `abs` and argument `arg` in radians.
+    pub fn from_polar(abs: T, arg: T) -> Complex<T> {
+        Complex::new(abs * arg.cos(), abs * arg.sin())
+    }
+
+    /// Returns the magnitude.
+    pub fn abs(&self) -> T {
+        self.norm_sqr().sqrt()
+    }
+
+    /// Returns the argument in radians, in the range -π to π.
+    pub fn arg(&self) -> T {
+        self.imag.atan2(self.real)
+    }
+}
+
+// The elementary functions below aren't used by the z² + c iteration, they're for alternative
+// formulas.
+#[allow(dead_code)]
+impl<T: Float> Complex<T> {
+    /// Returns the complex conjugate.
+    pub fn conj(self) -> Complex<T> {
+        Complex::new(self.real, -self.imag)
+    }
+
+    /// Returns e to the power of this number.
+    pub fn exp(self) -> Complex<T> {
+        Complex::from_polar(self.real.exp(), self.imag)
+    }
+
+    /// Returns the principal natural logarithm, whose imaginary part is in the range -π to π.
+    pub fn ln(self) -> Complex<T> {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    /// Returns this number to the power of `n`, found by repeated squaring so small powers
+    /// are exact products.
+    pub fn powi(self, n: i32) -> Complex<T> {
+        let mut result = Complex::new(T::from_f64(1.0), T::from_f64(0.0));
+        let mut square = self;
+        let mut exponent = n.unsigned_abs();
+
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result * square;
+            }
+            exponent >>= 1;
+
+            if exponent > 0 {
+                square = square * square;
+            }
+        }
+        if n < 0 { Complex::new(T::from_f64(1.0), T::from_f64(0.0)) / result } else { result }
+    }
+
+    /// Returns the principal value of this number to the real power `n`.
+    pub fn powf(self, n: T) -> Complex<T> {
+        if self.norm_sqr() == T::from_f64(0.0) {
+            return self;
+        }
+        Complex::from_polar(self.abs().powf(n), self.arg() * n)
+    }
+
+    /// Returns the principal value of this number to the complex power `n`.
+    pub fn powc(self, n: Complex<T>) -> Complex<T> {
+        if self.norm_sqr() == T::from_f64(0.0) {
+            return self;
+        }
+        (self.ln() * n).exp()
+    }
+
+    /// Returns the principal square root, whose real part isn't negative.
+    pub fn sqrt(self) -> Complex<T> {
+        let (half, zero) = (T::from_f64(0.5), T::from_f64(0.0));
+        let abs = self.abs();
+        let real = ((abs + self.real) * half).sqrt();
+        let imag = ((abs - self.real) * half).sqrt();
+        Complex::new(real, if self.imag < zero { -imag } else { imag })
+    }
 }
 
-impl Add for Complex {
-    type Output = Complex;
+impl<T: Real> Add for Complex<T> {
+    type Output = Complex<T>;
 
-    fn add(self, other: Complex) -> Complex {
+    fn add(self, other: Complex<T>) -> Complex<T> {
         Complex::new(self.real + other.real, self.imag + other.imag)
     }
 }
 
-impl Mul for Complex {
-    type Output = Complex;
+impl<T: Real> Sub for Complex<T> {
+    type Output = Complex<T>;
 
-    fn mul(self, other: Complex) -> Complex {
+    fn sub(self, other: Complex<T>) -> Complex<T> {
+        Complex::new(self.real - other.real, self.imag - other.imag)
+    }
+}
+
+impl<T: Real> Mul for Complex<T> {
+    type Output = Complex<T>;
+
+    fn mul(self, other: Complex<T>) -> Complex<T> {
         Complex::new(
             self.real * other.real - self.imag * other.imag,
             self.real * other.imag + self.imag * other.real
         )
     }
 }
+
+impl<T: Float> Div for Complex<T> {
+    type Output = Complex<T>;
+
+    fn div(self, other: Complex<T>) -> Complex<T> {
+        let divisor = other.norm_sqr();
+        Complex::new(
+            (self.real * other.real + self.imag * other.imag) / divisor,
+            (self.imag * other.real - self.real * other.imag) / divisor
+        )
+    }
+}
+
+impl<T: Float> Neg for Complex<T> {
+    type Output = Complex<T>;
+
+    fn neg(self) -> Complex<T> {
+        Complex::new(-self.real, -self.imag)
+    }
+}
+
+impl<T: Float> Add<T> for Complex<T> {
+    type Output = Complex<T>;
+
+    fn add(self, other: T) -> Complex<T> {
+        Complex::new(self.real + other, self.imag)
+    }
+}
+
+impl<T: Float> Sub<T> for Complex<T> {
+    type Output = Complex<T>;
+
+    fn sub(self, other: T) -> Complex<T> {
+        Complex::new(self.real - other, self.imag)
+    }
+}
+
+impl<T: Float> Mul<T> for Complex<T> {
+    type Output = Complex<T>;
+
+    fn mul(self, other: T) -> Complex<T> {
+        Complex::new(self.real * other, self.imag * other)
+    }
+}
+
+impl<T: Float> Div<T> for Complex<T> {
+    type Output = Complex<T>;
+
+    fn div(self, other: T) -> Complex<T> {
+        Complex::new(self.real / other, self.imag / other)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{E, FRAC_PI_2, FRAC_PI_4, PI};
+
+    fn assert_close(actual: Complex, expected: Complex) {
+        assert!((actual - expected).abs() < 1e-12, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(Complex::new(3.0, 5.0) + Complex::new(1.0, 2.0), Complex::new(4.0, 7.0));
+    }
+
+    #[test]
+    fn sub() {
+        assert_eq!(Complex::new(3.0, 5.0) - Complex::new(1.0, 2.0), Complex::new(2.0, 3.0));
+    }
+
+    #[test]
+    fn mul() {
+        assert_eq!(Complex::new(3.0, 4.0) * Complex::new(1.0, 2.0), Complex::new(-5.0, 10.0));
+    }
+
+    #[test]
+    fn div() {
+        assert_eq!(Complex::new(-5.0, 10.0) / Complex::new(1.0, 2.0), Complex::new(3.0, 4.0));
+    }
+
+    #[test]
+    fn neg() {
+        assert_eq!(-Complex::new(3.0, -4.0), Complex::new(-3.0, 4.0));
+    }
+
+    #[test]
+    fn scalar() {
+        let z = Complex::new(3.0, 6.0);
+        assert_eq!(z + 1.0, Complex::new(4.0, 6.0));
+        assert_eq!(z - 1.0, Complex::new(2.0, 6.0));
+        assert_eq!(z * 2.0, Complex::new(6.0, 12.0));
+        assert_eq!(z / 3.0, Complex::new(1.0, 2.0));
+    }
+
+    #[test]
+    fn abs() {
+        assert_eq!(Complex::new(3.0, 4.0).norm_sqr(), 25.0);
+        assert_eq!(Complex::new(3.0, 4.0).abs(), 5.0);
+    }
+
+    #[test]
+    fn arg() {
+        assert_eq!(Complex::new(1.0, 1.0).arg(), FRAC_PI_4);
+        assert_eq!(Complex::new(-1.0, 0.0).arg(), PI);
+    }
+
+    #[test]
+    fn from_polar() {
+        let z = Complex::from_polar(2.0, FRAC_PI_2);
+        assert!((z - Complex::new(0.0, 2.0)).abs() < 1e-15, "{:?}", z);
+        assert_eq!(Complex::from_polar(3.0, 0.0), Complex::new(3.0, 0.0));
+    }
+
+    #[test]
+    fn conj() {
+        assert_eq!(Complex::new(3.0, 4.0).conj(), Complex::new(3.0, -4.0));
+    }
+
+    #[test]
+    fn exp() {
+        assert_close(Complex::new(1.0, 0.0).exp(), Complex::new(E, 0.0));
+        assert_close(Complex::new(0.0, PI).exp(), Complex::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn ln() {
+        assert_close(Complex::new(E, 0.0).ln(), Complex::new(1.0, 0.0));
+        assert_close(Complex::new(0.0, -1.0).ln(), Complex::new(0.0, -FRAC_PI_2));
+        let z = Complex::new(0.3, -1.7);
+        assert_close(z.ln().exp(), z);
+    }
+
+    #[test]
+    fn powi() {
+        let z = Complex::new(1.0, 2.0);
+        assert_eq!(z.powi(0), Complex::new(1.0, 0.0));
+        assert_eq!(z.powi(1), z);
+        assert_eq!(z.powi(2), z * z);
+        assert_eq!(z.powi(5), z * z * z * z * z);
+        assert_close(z.powi(-2), Complex::new(1.0, 0.0) / (z * z));
+    }
+
+    #[test]
+    fn powf() {
+        let z = Complex::new(1.0, 2.0);
+        assert_close(z.powf(3.0), z * z * z);
+        assert_close(Complex::new(-4.0, 0.0).powf(0.5), Complex::new(0.0, 2.0));
+        assert_eq!(Complex::new(0.0, 0.0).powf(2.5), Complex::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn powc() {
+        let z = Complex::new(1.0, 2.0);
+        assert_close(z.powc(Complex::new(2.0, 0.0)), z * z);
+        // i to the power of i is e^(-π/2)
+        assert_close(Complex::new(0.0, 1.0).powc(Complex::new(0.0, 1.0)), Complex::new((-FRAC_PI_2).exp(), 0.0));
+        assert_eq!(Complex::new(0.0, 0.0).powc(z), Complex::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn sqrt() {
+        assert_eq!(Complex::new(-4.0, 0.0).sqrt(), Complex::new(0.0, 2.0));
+        assert_eq!(Complex::new(3.0, 4.0).sqrt(), Complex::new(2.0, 1.0));
+        assert_eq!(Complex::new(3.0, -4.0).sqrt(), Complex::new(2.0, -1.0));
+        let z = Complex::new(-0.7, 1.3);
+        assert_close(z.sqrt() * z.sqrt(), z);
+    }
+
+    #[test]
+    fn single_precision() {
+        let z = Complex::new(3.0f32, 4.0);
+        assert_eq!(z.abs(), 5.0f32);
+        assert_eq!(z * z, Complex::new(-7.0f32, 24.0));
+        assert_eq!(z / Complex::new(1.0f32, 2.0), Complex::new(2.2f32, -0.4));
+        assert_eq!(z.sqrt(), Complex::new(2.0f32, 1.0));
+    }
+}
@@ -0,0 +1,159 @@
+use complex::Complex;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A number represented as the unevaluated sum of two f64 values, giving about 106 bits of
+/// precision with the range of an f64.
+///
+/// The low part is never more than half a unit in the last place of the high part, so the
+/// high part is the nearest f64 to the number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DoubleDouble {
+    pub hi: f64,
+    pub lo: f64,
+}
+
+impl DoubleDouble {
+    pub fn new(hi: f64, lo: f64) -> DoubleDouble {
+        let (hi, lo) = quick_two_sum(hi, lo);
+        DoubleDouble { hi, lo }
+    }
+
+    pub fn from_f64(value: f64) -> DoubleDouble {
+        DoubleDouble { hi: value, lo: 0.0 }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.hi
+    }
+}
+
+/// Returns the sum of two f64 values and the rounding error of the sum.
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    let b_virtual = sum - a;
+    (sum, (a - (sum - b_virtual)) + (b - b_virtual))
+}
+
+/// Returns the sum of two f64 values and the rounding error of the sum, where `|a| >= |b|`.
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    (sum, b - (sum - a))
+}
+
+/// Returns the product of two f64 values and the rounding error of the product.
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let product = a * b;
+    (product, a.mul_add(b, -product))
+}
+
+impl Add for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn add(self, other: DoubleDouble) -> DoubleDouble {
+        let (hi, err) = two_sum(self.hi, other.hi);
+        let (lo, lo_err) = two_sum(self.lo, other.lo);
+        let (hi, err) = quick_two_sum(hi, err + lo);
+        DoubleDouble::new(hi, err + lo_err)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn sub(self, other: DoubleDouble) -> DoubleDouble {
+        self + -other
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn neg(self) -> DoubleDouble {
+        DoubleDouble { hi: -self.hi, lo: -self.lo }
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn mul(self, other: DoubleDouble) -> DoubleDouble {
+        let (product, err) = two_prod(self.hi, other.hi);
+        DoubleDouble::new(product, err + (self.hi * other.lo + self.lo * other.hi))
+    }
+}
+
+impl Mul<f64> for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn mul(self, other: f64) -> DoubleDouble {
+        let (product, err) = two_prod(self.hi, other);
+        DoubleDouble::new(product, err + self.lo * other)
+    }
+}
+
+impl Div for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn div(self, other: DoubleDouble) -> DoubleDouble {
+        // long division, each step finding the next 53 bits of the quotient
+        let q1 = self.hi / other.hi;
+        let remainder = self - other * q1;
+        let q2 = remainder.hi / other.hi;
+        let remainder = remainder - other * q2;
+        let q3 = remainder.hi / other.hi;
+        DoubleDouble::new(q1, q2) + DoubleDouble::from_f64(q3)
+    }
+}
+
+/// A complex number whose parts are double-doubles.
+pub type DdComplex = Complex<DoubleDouble>;
+
+impl DdComplex {
+    /// Returns the high parts of the real and imaginary parts.
+    pub fn hi(&self) -> Complex {
+        Complex::new(self.real.hi, self.imag.hi)
+    }
+}
+
+impl Add<Complex> for DdComplex {
+    type Output = DdComplex;
+
+    fn add(self, other: Complex) -> DdComplex {
+        DdComplex::new(self.real + DoubleDouble::from_f64(other.real), self.imag + DoubleDouble::from_f64(other.imag))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dd(value: f64) -> DoubleDouble {
+        DoubleDouble::from_f64(value)
+    }
+
+    #[test]
+    fn add() {
+        let sum = dd(1.0) + dd(1e-20);
+        assert_eq!(sum, DoubleDouble { hi: 1.0, lo: 1e-20 });
+        assert_eq!((sum - dd(1.0)).to_f64(), 1e-20);
+        assert_eq!(sum + -sum, dd(0.0));
+    }
+
+    #[test]
+    fn mul() {
+        // (1 + 2⁻⁴⁰)² = 1 + 2⁻³⁹ + 2⁻⁸⁰, which needs more than 53 bits
+        let value = dd(1.0) + dd(2f64.powi(-40));
+        assert_eq!(value * value, DoubleDouble { hi: 1.0 + 2f64.powi(-39), lo: 2f64.powi(-80) });
+        assert_eq!(value * 3.0, DoubleDouble { hi: 3.0 + 3.0 * 2f64.powi(-40), lo: 0.0 });
+    }
+
+    #[test]
+    fn div() {
+        let third = dd(1.0) / dd(3.0);
+        assert!(((third * 3.0) - dd(1.0)).to_f64().abs() < 1e-31);
+        assert_eq!(third.hi, 1.0 / 3.0);
+        assert!(third.lo != 0.0);
+    }
+}
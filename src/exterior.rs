@@ -0,0 +1,161 @@
+use average;
+use average::Average;
+use complex::Complex;
+use std::f64::consts::PI;
+use trap::OrbitTrap;
+use fractal::{self, Fractal};
+
+/// The number of field lines drawn by the field line overlay.
+const FIELD_LINES: f64 = 8.0;
+/// The width of the field lines, as a fraction of the distance between lines.
+const FIELD_LINE_WIDTH: f64 = 0.08;
+
+/// How the points outside the set are coloured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExteriorColouring {
+    /// The number of iterations before the point escaped.
+    Iterations,
+    /// The distance of the closest approach of the orbit to the orbit trap.
+    TrapDistance,
+    /// The angle of the closest approach of the orbit relative to the orbit trap.
+    TrapAngle,
+    /// An image texture mapped using the first orbit point inside a rectangle orbit trap.
+    /// Points whose orbits never enter the rectangle are coloured by their iterations.
+    TrapImage,
+    /// The smoothly interpolated average of a function over the orbit.
+    Average(Average),
+    /// Whether the final value of z is in the upper or lower half of the complex plane.
+    BinaryDecomposition,
+    /// The argument of the final value of z.
+    ExternalAngle,
+}
+
+impl ExteriorColouring {
+    /// Returns the colouring whose name is `name`, or `None` if there isn't one.
+    pub fn from_name(name: &str) -> Option<ExteriorColouring> {
+        match name {
+            "iterations" => Some(ExteriorColouring::Iterations),
+            "trap-distance" => Some(ExteriorColouring::TrapDistance),
+            "trap-angle" => Some(ExteriorColouring::TrapAngle),
+            "trap-image" => Some(ExteriorColouring::TrapImage),
+            "stripe" => Some(ExteriorColouring::Average(Average::Stripe)),
+            "triangle" => Some(ExteriorColouring::Average(Average::TriangleInequality)),
+            "curvature" => Some(ExteriorColouring::Average(Average::Curvature)),
+            "decomposition" => Some(ExteriorColouring::BinaryDecomposition),
+            "angle" => Some(ExteriorColouring::ExternalAngle),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the value used to colour `point` which is assumed to be outside the set of `fractal`.
+///
+/// The value of the `TrapImage` colouring is the texture coordinates, which are NaN if the
+/// orbit didn't enter the trap. The value of the other colourings is a real number stored in
+/// the real part.
+pub fn exterior_value<F: Fractal>(
+    fractal: &F,
+    point: Complex,
+    colouring: ExteriorColouring,
+    trap: &OrbitTrap,
+    max_iterations: u32,
+    escape_radius: f64,
+) -> Complex {
+    let trapped = || trap.trap_orbit(fractal, point, max_iterations, escape_radius);
+
+    match colouring {
+        ExteriorColouring::Iterations => Complex::new(trapped().iterations as f64, 0.0),
+        ExteriorColouring::TrapDistance => Complex::new(trapped().closest.abs(), 0.0),
+        // scale the angle to the range 0 to 1
+        ExteriorColouring::TrapAngle => Complex::new((trapped().closest.arg() + PI) / (2.0 * PI), 0.0),
+        ExteriorColouring::TrapImage => trapped().texture_coords.unwrap_or(Complex::new(f64::NAN, f64::NAN)),
+        ExteriorColouring::Average(average) =>
+            Complex::new(average::average_value(fractal, point, average, max_iterations, escape_radius), 0.0),
+        ExteriorColouring::BinaryDecomposition => {
+            let (_, z, _) = fractal::escape_orbit(fractal, point, max_iterations, escape_radius);
+            Complex::new(if z.imag >= 0.0 { 1.0 } else { 0.0 }, 0.0)
+        }
+        ExteriorColouring::ExternalAngle => {
+            let (_, z, _) = fractal::escape_orbit(fractal, point, max_iterations, escape_radius);
+            Complex::new(angle_fraction(z), 0.0)
+        }
+    }
+}
+
+/// Returns the argument of `z` as a fraction of a full turn, in the range 0 to 1.
+fn angle_fraction(z: Complex) -> f64 {
+    let fraction = z.arg() / (2.0 * PI);
+    fraction - fraction.floor()
+}
+
+/// Returns the factor applied to the colour of a point to draw field lines over the outside
+/// of the set, given the final value of z for the point.
+///
+/// The lines follow the external rays, where the argument of z is a multiple of a fraction of
+/// a turn, and the equipotentials, where the escape count is a whole number. Together they
+/// form a grid which shows how the exterior of the set is mapped onto the outside of a disk.
+pub fn field_line_factor(z: Complex, escape_radius: f64) -> f64 {
+    let ray = angle_fraction(z) * FIELD_LINES;
+    let ray_dist = (ray - ray.round()).abs();
+    // the fractional part of the smooth escape count
+    let potential = (1.0 + (escape_radius.ln() / z.abs().ln()).log2()).clamp(0.0, 1.0);
+    let potential_dist = potential.min(1.0 - potential);
+    let dist = ray_dist.min(potential_dist);
+    (dist / FIELD_LINE_WIDTH).min(1.0)
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fractal::Mandelbrot;
+
+    #[test]
+    fn trap_angle_in_range() {
+        let trap = OrbitTrap::Point { centre: Complex::new(0.0, 0.0) };
+        let value = exterior_value(&Mandelbrot, Complex::new(0.3, 0.6), ExteriorColouring::TrapAngle, &trap, 100, 2.0);
+        assert!(value.real >= 0.0 && value.real <= 1.0);
+    }
+
+    #[test]
+    fn from_name() {
+        assert_eq!(ExteriorColouring::from_name("curvature"), Some(ExteriorColouring::Average(Average::Curvature)));
+        assert_eq!(ExteriorColouring::from_name("foo"), None);
+    }
+
+    #[test]
+    fn binary_decomposition() {
+        let trap = OrbitTrap::Point { centre: Complex::new(0.0, 0.0) };
+        let value = |point| exterior_value(&Mandelbrot, point, ExteriorColouring::BinaryDecomposition, &trap, 100, 2.0).real;
+        // the orbit of c = 1 stays on the real axis
+        assert_eq!(value(Complex::new(1.0, 0.0)), 1.0);
+        // the orbit of 2i is 2i, -4 + 2i, 12 - 14i
+        assert_eq!(value(Complex::new(0.0, 2.0)), 1.0);
+        assert_eq!(value(Complex::new(0.0, -2.5)), 0.0);
+    }
+
+    #[test]
+    fn external_angle() {
+        let trap = OrbitTrap::Point { centre: Complex::new(0.0, 0.0) };
+        let value = |point| exterior_value(&Mandelbrot, point, ExteriorColouring::ExternalAngle, &trap, 100, 2.0).real;
+        assert_eq!(value(Complex::new(3.0, 0.0)), 0.0);
+        assert_eq!(value(Complex::new(-3.0, 0.0)), 0.5);
+    }
+
+    #[test]
+    fn field_lines() {
+        // on a ray
+        assert_eq!(field_line_factor(Complex::new(10.0, 0.0), 2.0), 0.0);
+        // between rays and away from the equipotentials
+        let between = Complex::from_polar(8.0, PI / FIELD_LINES);
+        assert_eq!(field_line_factor(between, 4.0), 1.0);
+    }
+
+    #[test]
+    fn trap_image_miss() {
+        let trap = OrbitTrap::Rectangle { corner: Complex::new(10.0, 10.0), size: Complex::new(1.0, 1.0) };
+        let value = exterior_value(&Mandelbrot, Complex::new(0.3, 0.6), ExteriorColouring::TrapImage, &trap, 100, 2.0);
+        assert!(value.real.is_nan());
+    }
+}
@@ -0,0 +1,506 @@
+use complex::Complex;
+use double_double::{DdComplex, DoubleDouble};
+use floatexp::{FeComplex, FloatExp};
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// The number of 32 bit limbs holding the integer part of a fixed-point number.
+const INT_LIMBS: usize = 2;
+/// The most limbs a fixed-point number can have. The 2048 fractional bits are enough for
+/// pixel sizes down to about 10⁻⁵⁹⁷ with the guard bits, far smaller than an f64 can hold.
+const MAX_LIMBS: usize = INT_LIMBS + 64;
+/// The fractional bits beyond the distance between samples which are kept so the rounding
+/// errors of the orbit points don't reach the bits that position the samples.
+const GUARD_BITS: usize = 64;
+/// The most digits after the decimal point which can affect a parsed number.
+const MAX_FRAC_DIGITS: i64 = 630;
+
+/// A fixed-point number with a 64 bit integer part and up to 2048 fractional bits, where the
+/// number of fractional bits used is chosen for the precision needed.
+///
+/// The result of an operation has as many limbs as the operand with the most limbs, and its
+/// fractional bits beyond them are truncated. The integer part must stay below 2⁶⁴, which
+/// limits the escape radius to about 2¹⁶.
+#[derive(Clone, Copy)]
+pub struct Fixed {
+    negative: bool,
+    /// The number of limbs in use. The limbs after them are always zero.
+    len: usize,
+    /// The limbs of the magnitude starting from the most significant, where limb `i` has a
+    /// weight of 2³²⁽¹⁻ⁱ⁾.
+    limbs: [u32; MAX_LIMBS],
+}
+
+impl Fixed {
+    /// Creates a number from its sign and magnitude, with zero always positive.
+    fn from_limbs(negative: bool, len: usize, limbs: [u32; MAX_LIMBS]) -> Fixed {
+        let negative = negative && limbs.iter().any(|&limb| limb != 0);
+        Fixed { negative, len, limbs }
+    }
+
+    /// Returns the number equal to `value`, which must be less than 2⁶⁴ in magnitude. It has as
+    /// few limbs as can hold the value exactly, unless it's too small to hold at all.
+    pub fn from_f64(value: f64) -> Fixed {
+        Fixed::from_floatexp(FloatExp::from_f64(value))
+    }
+
+    /// Returns the number equal to `value`, in the same way as `from_f64`.
+    pub fn from_floatexp(value: FloatExp) -> Fixed {
+        let mut limbs = [0; MAX_LIMBS];
+        let mut rest = value.abs();
+        let mut len = INT_LIMBS;
+
+        // each limb takes the next 32 bits of the value, which are found exactly because the
+        // weights are powers of two
+        for (idx, limb) in limbs.iter_mut().enumerate() {
+            if rest.mantissa == 0.0 {
+                break;
+            }
+            let exponent = weight_exponent(idx);
+            let limb_value = FloatExp::new(rest.mantissa, rest.exponent - exponent).to_f64().floor();
+            *limb = limb_value as u32;
+            rest = rest - FloatExp::new(limb_value, exponent);
+            len = len.max(idx + 1);
+        }
+        Fixed::from_limbs(value.mantissa < 0.0, len, limbs)
+    }
+
+    /// Parses a number in decimal notation with an optional exponent, for example
+    /// `-0.743643887037158704752191506114774` or `1.5e-120`. There can be any number of
+    /// digits, but the digits beyond the 2048 fractional bits don't change the number.
+    pub fn parse(value: &str) -> Option<Fixed> {
+        let value = value.trim();
+        let (negative, value) = match value.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, value.strip_prefix('+').unwrap_or(value)),
+        };
+        let (digits, exponent) = match value.find(['e', 'E']) {
+            Some(idx) => (&value[..idx], value[idx + 1..].parse::<i64>().ok()?),
+            None => (value, 0),
+        };
+        let (int_digits, frac_digits) = match digits.find('.') {
+            Some(idx) => (&digits[..idx], &digits[idx + 1..]),
+            None => (digits, ""),
+        };
+        if int_digits.is_empty() && frac_digits.is_empty() {
+            return None;
+        }
+        let digits = int_digits.chars().chain(frac_digits.chars())
+            .map(|digit| digit.to_digit(10))
+            .collect::<Option<Vec<_>>>()?;
+        // the index in the digits of the first digit after the decimal point
+        let point = (int_digits.len() as i64).checked_add(exponent)?;
+        let digit = |idx: i64| if idx < 0 { 0 } else { digits.get(idx as usize).map_or(0, |&digit| digit) };
+        let mut integer = 0u64;
+
+        // twenty more digits than there are would overflow unless they're all zeros
+        for idx in 0..point.min(digits.len() as i64 + 20) {
+            integer = integer.checked_mul(10)?.checked_add(u64::from(digit(idx)))?;
+        }
+        let mut limbs = [0; MAX_LIMBS];
+        let end = point.checked_add(MAX_FRAC_DIGITS)?;
+
+        // the fraction is built from its last digit, adding each digit to the fraction after it
+        // and dividing by ten
+        for idx in (point..end).rev() {
+            limbs[INT_LIMBS - 1] = digit(idx);
+            let mut remainder = 0u64;
+
+            for limb in limbs[INT_LIMBS - 1..].iter_mut() {
+                let value = (remainder << 32) | u64::from(*limb);
+                *limb = (value / 10) as u32;
+                remainder = value % 10;
+            }
+        }
+        limbs[0] = (integer >> 32) as u32;
+        limbs[1] = integer as u32;
+        let len = limbs.iter().rposition(|&limb| limb != 0).map_or(INT_LIMBS, |idx| (idx + 1).max(INT_LIMBS));
+        Some(Fixed::from_limbs(negative, len, limbs))
+    }
+
+    /// Returns the number with `len` limbs, truncating the fractional bits beyond them.
+    pub fn with_limbs(self, len: usize) -> Fixed {
+        let len = len.clamp(INT_LIMBS, MAX_LIMBS);
+        let mut limbs = self.limbs;
+
+        for limb in limbs[len..].iter_mut() {
+            *limb = 0;
+        }
+        Fixed::from_limbs(self.negative, len, limbs)
+    }
+
+    /// Returns an f64 close to the number, found from its most significant 96 bits. This is
+    /// accurate enough to compare orbit points with the escape radius and the periodicity
+    /// tolerance.
+    pub fn to_f64(self) -> f64 {
+        self.to_floatexp().to_f64()
+    }
+
+    /// Returns a `FloatExp` close to the number in the same way as `to_f64`, which keeps the
+    /// numbers too small for an f64.
+    pub fn to_floatexp(self) -> FloatExp {
+        let first = self.limbs[..self.len].iter().position(|&limb| limb != 0).unwrap_or(0);
+        let magnitude = self.limbs[first..self.len].iter().take(3).enumerate()
+            .fold(FloatExp::from_f64(0.0), |sum, (idx, &limb)| sum + FloatExp::new(f64::from(limb), weight_exponent(first + idx)));
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    /// Returns the double-double nearest to the number.
+    pub fn to_dd(self) -> DoubleDouble {
+        let magnitude = self.limbs[..self.len].iter().enumerate()
+            .fold(DoubleDouble::from_f64(0.0), |sum, (idx, &limb)| sum + DoubleDouble::from_f64(f64::from(limb) * weight(idx)));
+        if self.negative { -magnitude } else { magnitude }
+    }
+}
+
+/// Returns the power of two which is the weight of the limb at `idx`.
+fn weight_exponent(idx: usize) -> i64 {
+    32 * (INT_LIMBS as i64 - 1 - idx as i64)
+}
+
+/// Returns the weight of the limb at `idx`, which is zero for the limbs beyond the range of
+/// an f64.
+fn weight(idx: usize) -> f64 {
+    FloatExp::new(1.0, weight_exponent(idx)).to_f64()
+}
+
+/// Returns the number of fractional bits needed to calculate samples `spacing` apart.
+fn bits_for_spacing(spacing: FloatExp) -> usize {
+    (-spacing.log2()).ceil().max(0.0) as usize + GUARD_BITS
+}
+
+/// Returns the number of limbs needed to calculate samples `spacing` apart.
+pub fn limbs_for_spacing(spacing: FloatExp) -> usize {
+    (INT_LIMBS + bits_for_spacing(spacing).div_ceil(32)).min(MAX_LIMBS)
+}
+
+/// Returns true if the fractional bits of the largest fixed-point numbers can position
+/// samples `spacing` apart, which must be positive.
+pub fn holds_spacing(spacing: FloatExp) -> bool {
+    bits_for_spacing(spacing) <= (MAX_LIMBS - INT_LIMBS) * 32
+}
+
+/// Returns the sum of the first `len` limbs of two magnitudes.
+fn add_magnitudes(a: &[u32; MAX_LIMBS], b: &[u32; MAX_LIMBS], len: usize) -> [u32; MAX_LIMBS] {
+    let mut sum = [0; MAX_LIMBS];
+    let mut carry = 0;
+
+    for idx in (0..len).rev() {
+        let value = u64::from(a[idx]) + u64::from(b[idx]) + carry;
+        sum[idx] = value as u32;
+        carry = value >> 32;
+    }
+    sum
+}
+
+/// Returns the difference of the first `len` limbs of two magnitudes, where `a` is at least `b`.
+fn sub_magnitudes(a: &[u32; MAX_LIMBS], b: &[u32; MAX_LIMBS], len: usize) -> [u32; MAX_LIMBS] {
+    let mut difference = [0; MAX_LIMBS];
+    let mut borrow = false;
+
+    for idx in (0..len).rev() {
+        let (value, borrow_a) = a[idx].overflowing_sub(b[idx]);
+        let (value, borrow_b) = value.overflowing_sub(u32::from(borrow));
+        difference[idx] = value;
+        borrow = borrow_a || borrow_b;
+    }
+    difference
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        let len = self.len.max(other.len);
+
+        if self.negative == other.negative {
+            Fixed::from_limbs(self.negative, len, add_magnitudes(&self.limbs, &other.limbs, len))
+        } else if self.limbs[..len] >= other.limbs[..len] {
+            Fixed::from_limbs(self.negative, len, sub_magnitudes(&self.limbs, &other.limbs, len))
+        } else {
+            Fixed::from_limbs(other.negative, len, sub_magnitudes(&other.limbs, &self.limbs, len))
+        }
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        self + -other
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed::from_limbs(!self.negative, self.len, self.limbs)
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: Fixed) -> Fixed {
+        let len = self.len.max(other.len);
+        // the product of limbs i and j is added to column i + j, which holds limb
+        // i + j - INT_LIMBS + 1 of the result, with its high half carried into the column before.
+        // Only one column beyond the last limb of the result is calculated, so the result can
+        // be slightly less than the truncated product.
+        let last = len + INT_LIMBS - 1;
+        let mut columns = [0u64; MAX_LIMBS + INT_LIMBS];
+
+        for i in 0..len {
+            if self.limbs[i] == 0 {
+                continue;
+            }
+            for j in 0..len.min(last + 1 - i) {
+                let product = u64::from(self.limbs[i]) * u64::from(other.limbs[j]);
+                columns[i + j] += product & 0xffff_ffff;
+
+                if i + j > 0 {
+                    columns[i + j - 1] += product >> 32;
+                }
+            }
+        }
+        for idx in (1..=last).rev() {
+            columns[idx - 1] += columns[idx] >> 32;
+            columns[idx] &= 0xffff_ffff;
+        }
+        let mut limbs = [0; MAX_LIMBS];
+
+        for (idx, limb) in limbs[..len].iter_mut().enumerate() {
+            *limb = columns[idx + INT_LIMBS - 1] as u32;
+        }
+        Fixed::from_limbs(self.negative != other.negative, len, limbs)
+    }
+}
+
+impl PartialEq for Fixed {
+    /// Numbers are equal if their values are equal, whatever their numbers of limbs.
+    fn eq(&self, other: &Fixed) -> bool {
+        self.negative == other.negative && self.limbs == other.limbs
+    }
+}
+
+impl fmt::Debug for Fixed {
+    /// Formats the number in decimal, rounded to as many digits as its limbs hold.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut integer = (u64::from(self.limbs[0]) << 32) | u64::from(self.limbs[1]);
+        let mut fraction = self.limbs;
+        let mut digits = Vec::new();
+
+        // each digit is the integer part of the fraction multiplied by ten. Half a unit in the
+        // last digit shown is more than the last bit, so the truncation errors are rounded away,
+        // and one more digit is found for the rounding.
+        let bits = (self.len - INT_LIMBS) as f64 * 32.0;
+        let shown = ((bits - 1.0) * 2f64.log10()).floor() as usize;
+
+        for _ in 0..shown + 1 {
+            let mut carry = 0u64;
+
+            for limb in fraction[INT_LIMBS..self.len].iter_mut().rev() {
+                let value = u64::from(*limb) * 10 + carry;
+                *limb = value as u32;
+                carry = value >> 32;
+            }
+            digits.push(carry as u8);
+        }
+        let round_up = digits.pop().is_some_and(|digit| digit >= 5);
+
+        if round_up {
+            match digits.iter().rposition(|&digit| digit != 9) {
+                Some(idx) => {
+                    digits[idx] += 1;
+                    digits.truncate(idx + 1);
+                }
+                None => {
+                    digits.clear();
+                    integer += 1;
+                }
+            }
+        }
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        let digits = digits.iter().map(|&digit| char::from(b'0' + digit)).collect::<String>();
+        write!(f, "{}{}.{}", if self.negative { "-" } else { "" }, integer, if digits.is_empty() { "0" } else { &digits })
+    }
+}
+
+/// A complex number whose parts are fixed-point numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedComplex {
+    pub real: Fixed,
+    pub imag: Fixed,
+}
+
+impl FixedComplex {
+    pub fn new(real: Fixed, imag: Fixed) -> FixedComplex {
+        FixedComplex { real, imag }
+    }
+
+    pub fn from_complex(value: Complex) -> FixedComplex {
+        FixedComplex::new(Fixed::from_f64(value.real), Fixed::from_f64(value.imag))
+    }
+
+    /// Parses a complex number from its real and imaginary parts in decimal notation separated
+    /// by a comma, for example `-0.7436438870371587047521915061147740,0.1318259042053119704931`.
+    pub fn parse(value: &str) -> Option<FixedComplex> {
+        let mut parts = value.split(',');
+        let real = Fixed::parse(parts.next()?)?;
+        let imag = Fixed::parse(parts.next()?)?;
+
+        match parts.next() {
+            None => Some(FixedComplex::new(real, imag)),
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the number with `len` limbs in each part.
+    pub fn with_limbs(self, len: usize) -> FixedComplex {
+        FixedComplex::new(self.real.with_limbs(len), self.imag.with_limbs(len))
+    }
+
+    pub fn to_dd(self) -> DdComplex {
+        DdComplex::new(self.real.to_dd(), self.imag.to_dd())
+    }
+
+    /// Returns the nearest complex number with f64 parts.
+    pub fn to_complex(self) -> Complex {
+        self.to_dd().hi()
+    }
+
+    pub fn to_fe_complex(self) -> FeComplex {
+        FeComplex::new(self.real.to_floatexp(), self.imag.to_floatexp())
+    }
+}
+
+impl Add<FeComplex> for FixedComplex {
+    type Output = FixedComplex;
+
+    fn add(self, other: FeComplex) -> FixedComplex {
+        FixedComplex::new(self.real + Fixed::from_floatexp(other.real), self.imag + Fixed::from_floatexp(other.imag))
+    }
+}
+
+impl Sub for FixedComplex {
+    type Output = FixedComplex;
+
+    fn sub(self, other: FixedComplex) -> FixedComplex {
+        FixedComplex::new(self.real - other.real, self.imag - other.imag)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(value: &str) -> Fixed {
+        Fixed::parse(value).unwrap()
+    }
+
+    #[test]
+    fn from_f64() {
+        for &value in &[0.0, 1.0, -2.5, 0.1, -0.743643887037158, 123456789.125, -1e-200] {
+            assert_eq!(Fixed::from_f64(value).to_f64(), value);
+        }
+        for &value in &[-0.743643887037158, 1e-30, 1e-290] {
+            assert_eq!(Fixed::from_f64(value).to_dd().hi, value);
+        }
+        assert_eq!(Fixed::from_f64(0.5).len, INT_LIMBS + 1);
+        assert_eq!(Fixed::from_f64(-0.0), Fixed::from_f64(0.0));
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(fixed("1.25") + fixed("-3.5"), fixed("-2.25"));
+        assert_eq!(fixed("-1.25") + fixed("3.5"), fixed("2.25"));
+        assert_eq!(fixed("-1.25") + fixed("-3.5"), fixed("-4.75"));
+        assert_eq!(fixed("1.25") - fixed("1.25"), Fixed::from_f64(0.0));
+        let tiny = fixed("1e-300");
+        assert_eq!((fixed("1") + tiny) - fixed("1"), tiny);
+    }
+
+    #[test]
+    fn mul() {
+        assert_eq!(fixed("1.5") * fixed("-2.25"), fixed("-3.375"));
+        assert_eq!(fixed("-0.5") * fixed("-0.5"), fixed("0.25"));
+        // (1 + 10⁻¹⁰⁰)² = 1 + 2×10⁻¹⁰⁰ + 10⁻²⁰⁰
+        let value = fixed("1") + fixed("1e-100");
+        let expected = fixed("1") + fixed("2e-100") + fixed("1e-200");
+        assert!((value * value - expected).to_dd().hi.abs() < 1e-300);
+        // the fractional bits beyond the limbs are truncated
+        let third = fixed("0.333333333333333333333333333333").with_limbs(4);
+        assert!(((third * fixed("3")).with_limbs(4) - fixed("1")).to_f64().abs() < 1e-18);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(fixed("1.5"), Fixed::from_f64(1.5));
+        assert_eq!(fixed("-25e-1"), Fixed::from_f64(-2.5));
+        assert_eq!(fixed("+.5"), Fixed::from_f64(0.5));
+        assert_eq!(fixed("7"), Fixed::from_f64(7.0));
+        assert_eq!(fixed("0.00012e4"), fixed("1.2"));
+        assert_eq!(fixed("12e10").to_f64(), 12e10);
+        // digits beyond the precision of a double-double are kept
+        let digits = "-0.7436438870371587047521915061147740000000000000000000000000000000000001";
+        let value = fixed(digits);
+        assert_eq!(value.to_dd().hi, digits.parse::<f64>().unwrap());
+        assert!(value != fixed("-0.743643887037158704752191506114774"));
+        assert!((fixed("1e-290").to_dd().hi / 1e-290 - 1.0).abs() < 1e-15);
+        assert_eq!(Fixed::parse(""), None);
+        assert_eq!(Fixed::parse("."), None);
+        assert_eq!(Fixed::parse("1.2.3"), None);
+        assert_eq!(Fixed::parse("1e"), None);
+        assert_eq!(Fixed::parse("1e30"), None);
+        assert_eq!(Fixed::parse("0e9223372036854775807"), None);
+        assert_eq!(Fixed::parse("0e9223372036854775806"), None);
+    }
+
+    #[test]
+    fn beyond_f64() {
+        let tiny = FloatExp::parse("1.5e-400").unwrap();
+        let value = Fixed::from_floatexp(tiny);
+        assert_eq!(value.to_f64(), 0.0);
+        assert_eq!(value.to_floatexp(), tiny);
+        assert_eq!(((fixed("1") + value) - fixed("1")).to_floatexp(), tiny);
+        assert!(((fixed("1.5e-400").to_floatexp() / tiny).to_f64() - 1.0).abs() < 1e-15);
+        assert_eq!(Fixed::from_floatexp(-tiny), -value);
+    }
+
+    #[test]
+    fn debug_format() {
+        assert_eq!(format!("{:?}", fixed("-2.375")), "-2.375");
+        assert_eq!(format!("{:?}", fixed("3")), "3.0");
+        assert_eq!(format!("{:?}", fixed("0.1234567890123456789012345678901234567890")), "0.123456789012345678901234567890123456789");
+        assert_eq!(format!("{:?}", fixed("0.999999999999999999999999999").with_limbs(3)), "1.0");
+    }
+
+    #[test]
+    fn limbs_for_spacing() {
+        assert_eq!(super::limbs_for_spacing(FloatExp::from_f64(1.0)), INT_LIMBS + 2);
+        assert_eq!(super::limbs_for_spacing(FloatExp::from_f64(1e-100)), INT_LIMBS + 13);
+        assert_eq!(super::limbs_for_spacing(FloatExp::parse("1e-400").unwrap()), INT_LIMBS + 44);
+        assert_eq!(super::limbs_for_spacing(FloatExp::parse("1e-1000").unwrap()), MAX_LIMBS);
+    }
+
+    #[test]
+    fn holds_spacing() {
+        assert!(super::holds_spacing(FloatExp::parse("1e-400").unwrap()));
+        assert!(super::holds_spacing(FloatExp::parse("1e-597").unwrap()));
+        assert!(!super::holds_spacing(FloatExp::parse("1e-598").unwrap()));
+        assert!(!super::holds_spacing(FloatExp::parse("1e-1000").unwrap()));
+    }
+
+    #[test]
+    fn parse_complex() {
+        assert_eq!(FixedComplex::parse("-1.5, 0.25"), Some(FixedComplex::from_complex(Complex::new(-1.5, 0.25))));
+        assert_eq!(FixedComplex::parse("-1.5"), None);
+        assert_eq!(FixedComplex::parse("0e9223372036854775807,0"), None);
+        assert_eq!(FixedComplex::parse("-1.5,0.25,1"), None);
+    }
+}
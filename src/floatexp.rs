@@ -0,0 +1,227 @@
+use complex::Complex;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// The largest decimal exponent a parsed number can have, which is far beyond the deepest zoom
+/// the fixed-point numbers can position samples at, but keeps parsing quick.
+const MAX_DECIMAL_EXPONENT: i64 = 10_000;
+
+/// A number represented as an f64 mantissa scaled by a separate power of two, which has the
+/// precision of an f64 with a range far beyond it. This is used for the tiny distances between
+/// samples in zooms deeper than about 10⁻³⁰⁸, where f64 underflows.
+///
+/// The mantissa is between 0.5 and 1 in magnitude unless the number is zero, so the arithmetic
+/// gives exactly the same results as f64 while the numbers are in its range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatExp {
+    pub mantissa: f64,
+    pub exponent: i64,
+}
+
+impl FloatExp {
+    /// Creates the number `mantissa` × 2^`exponent`.
+    pub fn new(mantissa: f64, exponent: i64) -> FloatExp {
+        if mantissa == 0.0 || !mantissa.is_finite() {
+            return FloatExp { mantissa, exponent: 0 };
+        }
+        let bits = mantissa.to_bits();
+        let biased = ((bits >> 52) & 0x7ff) as i64;
+
+        // a subnormal mantissa is scaled up so its exponent can be found from its bits
+        if biased == 0 {
+            return FloatExp::new(mantissa * pow2(64), exponent - 64);
+        }
+        let mantissa = f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52));
+        FloatExp { mantissa, exponent: exponent + biased - 1022 }
+    }
+
+    pub fn from_f64(value: f64) -> FloatExp {
+        FloatExp::new(value, 0)
+    }
+
+    /// Parses a number in decimal notation with an optional exponent, which can be far outside
+    /// the range of an f64, for example `1.5e-1000`. Exponents beyond ±10000 aren't accepted.
+    pub fn parse(value: &str) -> Option<FloatExp> {
+        let value = value.trim();
+        let (digits, exponent) = match value.find(['e', 'E']) {
+            Some(idx) => (&value[..idx], value[idx + 1..].parse::<i64>().ok()?),
+            None => (value, 0),
+        };
+        if exponent.abs() > MAX_DECIMAL_EXPONENT {
+            return None;
+        }
+        // the power of ten is split into a remainder which f64 can parse and a multiple of 10³⁰⁰,
+        // which is applied afterwards
+        let remainder = exponent % 300;
+        let mut number = FloatExp::from_f64(format!("{}e{}", digits, remainder).parse().ok()?);
+        let pow10 = FloatExp::from_f64(if exponent < 0 { 1e-300 } else { 1e300 });
+
+        for _ in 0..(exponent / 300).abs() {
+            number = number * pow10;
+        }
+        Some(number)
+    }
+
+    /// Returns the nearest f64 to the number, which is zero or infinite if it's out of range.
+    pub fn to_f64(self) -> f64 {
+        // scaling in two steps keeps the first step exact, so only the result is rounded
+        let exponent = self.exponent.clamp(-1100, 1100);
+        self.mantissa * pow2(exponent / 2) * pow2(exponent - exponent / 2)
+    }
+
+    pub fn abs(self) -> FloatExp {
+        FloatExp { mantissa: self.mantissa.abs(), ..self }
+    }
+
+    /// Returns the base 2 logarithm of the number.
+    pub fn log2(self) -> f64 {
+        self.mantissa.log2() + self.exponent as f64
+    }
+}
+
+/// Returns 2^`exponent`, where the exponent is in the range of a normal f64.
+fn pow2(exponent: i64) -> f64 {
+    f64::from_bits(((exponent + 1023) as u64) << 52)
+}
+
+impl Add for FloatExp {
+    type Output = FloatExp;
+
+    fn add(self, other: FloatExp) -> FloatExp {
+        if self.mantissa == 0.0 {
+            return other;
+        }
+        if other.mantissa == 0.0 {
+            return self;
+        }
+        let (larger, smaller) = if self.exponent >= other.exponent { (self, other) } else { (other, self) };
+        let shift = smaller.exponent - larger.exponent;
+
+        // the smaller number is less than half a unit in the last place of the larger one, so
+        // the sum rounds to the larger one
+        if shift < -64 {
+            return larger;
+        }
+        FloatExp::new(larger.mantissa + smaller.mantissa * pow2(shift), larger.exponent)
+    }
+}
+
+impl Sub for FloatExp {
+    type Output = FloatExp;
+
+    fn sub(self, other: FloatExp) -> FloatExp {
+        self + -other
+    }
+}
+
+impl Neg for FloatExp {
+    type Output = FloatExp;
+
+    fn neg(self) -> FloatExp {
+        FloatExp { mantissa: -self.mantissa, ..self }
+    }
+}
+
+impl Mul for FloatExp {
+    type Output = FloatExp;
+
+    fn mul(self, other: FloatExp) -> FloatExp {
+        FloatExp::new(self.mantissa * other.mantissa, self.exponent + other.exponent)
+    }
+}
+
+impl Mul<f64> for FloatExp {
+    type Output = FloatExp;
+
+    fn mul(self, other: f64) -> FloatExp {
+        self * FloatExp::from_f64(other)
+    }
+}
+
+impl Div for FloatExp {
+    type Output = FloatExp;
+
+    fn div(self, other: FloatExp) -> FloatExp {
+        FloatExp::new(self.mantissa / other.mantissa, self.exponent - other.exponent)
+    }
+}
+
+/// A complex number whose parts are `FloatExp`s, used for the offsets of samples from the
+/// origin of a set.
+pub type FeComplex = Complex<FloatExp>;
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fe(value: f64) -> FloatExp {
+        FloatExp::from_f64(value)
+    }
+
+    #[test]
+    fn new() {
+        assert_eq!(fe(6.0), FloatExp { mantissa: 0.75, exponent: 3 });
+        assert_eq!(fe(-0.1).to_f64(), -0.1);
+        assert_eq!(fe(0.0), FloatExp { mantissa: 0.0, exponent: 0 });
+        assert_eq!(fe(5e-324), FloatExp { mantissa: 0.5, exponent: -1073 });
+        assert_eq!(fe(5e-324).to_f64(), 5e-324);
+        assert_eq!(FloatExp::new(0.75, -2000).to_f64(), 0.0);
+        assert_eq!(FloatExp::new(0.75, 2000).to_f64(), f64::INFINITY);
+    }
+
+    #[test]
+    fn matches_f64() {
+        // the mantissas are rounded exactly as f64 would round the numbers
+        let values = [1.0 / 3.0, -7.25e10, 2f64.sqrt(), -1e-300, 0.0];
+
+        for &a in &values {
+            for &b in &values {
+                assert_eq!((fe(a) + fe(b)).to_f64(), a + b);
+                assert_eq!((fe(a) - fe(b)).to_f64(), a - b);
+                assert_eq!((fe(a) * fe(b)).to_f64(), a * b);
+                assert_eq!((fe(a) * b).to_f64(), a * b);
+                if b != 0.0 {
+                    assert_eq!((fe(a) / fe(b)).to_f64(), a / b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn beyond_f64() {
+        let tiny = fe(1e-300) * fe(1e-300);
+        assert_eq!(tiny.to_f64(), 0.0);
+        assert_eq!((tiny / fe(1e-300)).to_f64(), 1e-300);
+        assert_eq!(((tiny + tiny) / tiny).to_f64(), 2.0);
+        assert_eq!(fe(1.0) + tiny, fe(1.0));
+        assert!((tiny.log2() + 600.0 * 10f64.log2()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(FloatExp::parse("1e-20"), Some(fe(1e-20)));
+        assert_eq!(FloatExp::parse("-2.5"), Some(fe(-2.5)));
+        let tiny = FloatExp::parse("1.5e-1000").unwrap();
+        assert!(((tiny / FloatExp::parse("1e-1000").unwrap()).to_f64() - 1.5).abs() < 1e-14);
+        assert!((tiny.log2() - (1.5f64.log10() - 1000.0) / 2f64.log10()).abs() < 1e-9);
+        assert!(FloatExp::parse("1e-10000").is_some());
+        assert_eq!(FloatExp::parse("1e-10001"), None);
+        assert_eq!(FloatExp::parse("1e-999999999999"), None);
+        assert_eq!(FloatExp::parse("e-20"), None);
+        assert_eq!(FloatExp::parse("1e"), None);
+    }
+
+    #[test]
+    fn complex() {
+        let (a, b) = (Complex::new(1.0, -2.0), Complex::new(0.5, 0.25));
+        let (fe_a, fe_b) = (FeComplex::from_complex(a), FeComplex::from_complex(b));
+        assert_eq!((fe_a + fe_b).to_complex(), a + b);
+        assert_eq!((fe_a - fe_b).to_complex(), a - b);
+        assert_eq!((fe_a * fe_b).to_complex(), a * b);
+        assert_eq!(fe_a.norm_sqr().to_f64(), 5.0);
+        // the square of a tiny number is far too small for an f64
+        let tiny = FeComplex::new(FloatExp::parse("3e-400").unwrap(), FloatExp::parse("4e-400").unwrap());
+        assert!((((tiny * tiny).norm_sqr() / (tiny.norm_sqr() * tiny.norm_sqr())).to_f64() - 1.0).abs() < 1e-15);
+    }
+}
@@ -0,0 +1,314 @@
+use complex::Complex;
+use interior::{self, InteriorColouring};
+use precision::{self, Float};
+
+/// A formula iterated for each point of the plane, whose escape iterations make up the image.
+///
+/// Only the orbit needs to be defined: the first point, the step to the next point and the
+/// test for escaping. They're generic over the float type so the set can be calculated in f32
+/// or f64, and every formula goes through the same set calculation. Only the Mandelbrot set
+/// has kernels written for it, which are vectorised and can zoom beyond f64.
+pub trait Fractal: Copy + Send + Sync + 'static {
+    /// Returns the first point of the orbit of the point `c` of the plane.
+    fn init<T: Float>(&self, c: Complex<T>) -> Complex<T>;
+
+    /// Returns the orbit point after `z` in the orbit of the point `c`.
+    fn step<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T>;
+
+    /// Returns the constant added at each step of the orbit of the point `c`, which bounds the
+    /// triangle inequality average.
+    fn constant(&self, c: Complex) -> Complex {
+        c
+    }
+
+    /// Returns true if the orbit has escaped once it reaches `z`, given the set's escape
+    /// radius.
+    fn bailout<T: Float>(&self, z: Complex<T>, escape_radius: f64) -> bool {
+        z.norm_sqr() > T::from_f64(escape_radius * escape_radius)
+    }
+
+    /// Returns the derivative of the orbit point after `z` with respect to the point of the
+    /// plane, given the derivative `dz` of `z`, or `None` if the formula doesn't have one. The
+    /// derivative of the first orbit point is one. Lighting needs the derivative.
+    fn derivative<T: Float>(&self, _z: Complex<T>, _dz: Complex<T>) -> Option<Complex<T>> {
+        None
+    }
+
+    /// Returns true if the set is its own mirror image in the real axis, so the rows on one
+    /// side of the axis can be mirrored from the other.
+    fn symmetric(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the point `c` is known to be in the set without iterating it, which is
+    /// used by the interior check.
+    fn in_known_interior(&self, _c: Complex) -> bool {
+        false
+    }
+
+    /// Returns true if the formula is the Mandelbrot set's z² + c starting from c, which is
+    /// calculated with the kernels written for it. The other formulas are iterated one point
+    /// at a time, and only in f32 or f64.
+    fn is_mandelbrot(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the interior can be coloured with `mode`. Every formula can colour it
+    /// black or by the magnitude of the last orbit point, but the other modes depend on the
+    /// attracting cycles of the formula.
+    fn has_interior(&self, mode: InteriorColouring) -> bool {
+        matches!(mode, InteriorColouring::Black | InteriorColouring::FinalMagnitude)
+    }
+
+    /// Returns the value used to colour the point `c` which is assumed to be inside the set,
+    /// with a mode the formula has.
+    fn interior_value(&self, c: Complex, mode: InteriorColouring, max_iterations: u32) -> f64 {
+        match mode {
+            InteriorColouring::Black => 0.0,
+            InteriorColouring::FinalMagnitude => {
+                let mut z = self.init(c);
+
+                for _ in 1..max_iterations {
+                    z = self.step(z, c);
+                }
+                z.abs()
+            }
+            mode => panic!("The interior of the formula can't be coloured by {:?}", mode),
+        }
+    }
+}
+
+/// The Mandelbrot set, which iterates z² + c starting from c.
+///
+/// It's calculated using the vectorised kernels, in higher precisions or relative to
+/// reference orbits in deep zooms, and with the main cardioid and period-2 bulb known to be
+/// inside the set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mandelbrot;
+
+impl Fractal for Mandelbrot {
+    fn init<T: Float>(&self, c: Complex<T>) -> Complex<T> {
+        c
+    }
+
+    fn step<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        z * z + c
+    }
+
+    fn derivative<T: Float>(&self, z: Complex<T>, dz: Complex<T>) -> Option<Complex<T>> {
+        // z' -> 2zz' + 1
+        let two = T::from_f64(2.0);
+        Some(Complex::new(
+            two * (z.real * dz.real - z.imag * dz.imag) + T::from_f64(1.0),
+            two * (z.real * dz.imag + z.imag * dz.real)
+        ))
+    }
+
+    fn symmetric(&self) -> bool {
+        true
+    }
+
+    fn in_known_interior(&self, c: Complex) -> bool {
+        precision::in_cardioid_or_bulb(c)
+    }
+
+    fn is_mandelbrot(&self) -> bool {
+        true
+    }
+
+    fn has_interior(&self, _mode: InteriorColouring) -> bool {
+        true
+    }
+
+    fn interior_value(&self, c: Complex, mode: InteriorColouring, max_iterations: u32) -> f64 {
+        interior::interior_value(c, mode, max_iterations)
+    }
+}
+
+/// The Julia set for the constant `c`, which iterates z² + c starting from the point of the
+/// plane.
+///
+/// It's calculated one point at a time, so it can only be calculated in f32 or f64. The set
+/// is only its own mirror image in the real axis if `c` is real.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Julia {
+    pub c: Complex,
+}
+
+impl Fractal for Julia {
+    fn init<T: Float>(&self, point: Complex<T>) -> Complex<T> {
+        point
+    }
+
+    fn step<T: Float>(&self, z: Complex<T>, _point: Complex<T>) -> Complex<T> {
+        z * z + Complex::from_complex(self.c)
+    }
+
+    fn constant(&self, _point: Complex) -> Complex {
+        self.c
+    }
+
+    fn derivative<T: Float>(&self, z: Complex<T>, dz: Complex<T>) -> Option<Complex<T>> {
+        // z' -> 2zz'
+        let two = T::from_f64(2.0);
+        Some(Complex::new(
+            two * (z.real * dz.real - z.imag * dz.imag),
+            two * (z.real * dz.imag + z.imag * dz.real)
+        ))
+    }
+
+    fn symmetric(&self) -> bool {
+        self.c.imag == 0.0
+    }
+}
+
+/// Returns the escape iterations of the point `c` along with the period of the cycle its
+/// orbit is attracted to, calculated in the precision of `T` using Brent's algorithm like
+/// `precision::escape_iterations` if `tolerance` isn't `None`. Zero is returned for the
+/// iterations if the point doesn't escape, and for the period if no cycle is found.
+pub fn escape_iterations<F: Fractal, T: Float>(fractal: &F, c: Complex<T>, max_iterations: u32, escape_radius: f64, tolerance: Option<f64>) -> (u32, u32) {
+    let (iterations, period, _) = escape_iterations_counted(fractal, c, max_iterations, escape_radius, tolerance);
+    (iterations, period)
+}
+
+/// Returns the escape iterations and period of the point `c` like `escape_iterations`, along
+/// with the number of iterations calculated before the orbit escaped or was found to be
+/// attracted to a cycle, which is the cost of calculating the point.
+pub fn escape_iterations_counted<F: Fractal, T: Float>(
+    fractal: &F,
+    c: Complex<T>,
+    max_iterations: u32,
+    escape_radius: f64,
+    tolerance: Option<f64>,
+) -> (u32, u32, u32) {
+    let tolerance_value = tolerance.map(|tolerance| T::from_f64(tolerance * tolerance));
+    let mut z = fractal.init(c);
+    let mut saved = z;
+    let mut save_iter = 0;
+    let mut next_save = 1u32;
+
+    for i in 0..max_iterations {
+        if fractal.bailout(z, escape_radius) {
+            return (i, 0, i);
+        }
+        z = fractal.step(z, c);
+
+        if let Some(tolerance_value) = tolerance_value {
+            if (z - saved).norm_sqr() < tolerance_value {
+                return (0, i + 1 - save_iter, i + 1);
+            }
+            if i + 1 == next_save {
+                saved = z;
+                save_iter = i + 1;
+                next_save = next_save.saturating_mul(2);
+            }
+        }
+    }
+    (0, 0, max_iterations)
+}
+
+/// Returns the escape iterations of the point `c` along with the last orbit point and its
+/// derivative, if the formula has one.
+pub fn escape_orbit<F: Fractal>(fractal: &F, c: Complex, max_iterations: u32, escape_radius: f64) -> (u32, Complex, Option<Complex>) {
+    let mut z = fractal.init(c);
+    let one = Complex::new(1.0, 0.0);
+    // the derivative of the first orbit point is only known if the formula has one
+    let mut dz = fractal.derivative(z, one).map(|_| one);
+
+    for i in 0..max_iterations {
+        if fractal.bailout(z, escape_radius) {
+            return (i, z, dz);
+        }
+        dz = dz.and_then(|dz| fractal.derivative(z, dz));
+        z = fractal.step(z, c);
+    }
+    (0, z, dz)
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use precision::Precision;
+    use {calc_set, calc_set_parallel, SetDefinition};
+
+    /// The Mandelbrot set defined only by its orbit, which is calculated by the defaults.
+    #[derive(Clone, Copy)]
+    struct Formula;
+
+    impl Fractal for Formula {
+        fn init<T: Float>(&self, c: Complex<T>) -> Complex<T> {
+            c
+        }
+
+        fn step<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+            z * z + c
+        }
+    }
+
+    #[test]
+    fn defaults_match_mandelbrot() {
+        for i in 0..200 {
+            let point = Complex::new(-2.0 + 0.0151 * i as f64, 1.1 - 0.0107 * i as f64);
+            assert_eq!(escape_iterations(&Formula, point, 500, 2.0, None), precision::escape_iterations(point, 500, 2.0, None));
+            assert_eq!(escape_iterations(&Formula, point, 500, 2.0, Some(1e-6)), precision::escape_iterations(point, 500, 2.0, Some(1e-6)));
+            let (iterations, z, dz) = escape_orbit(&Mandelbrot, point, 500, 2.0);
+            assert_eq!(escape_orbit(&Formula, point, 500, 2.0), (iterations, z, None));
+            assert_eq!(iterations, precision::escape_iterations(point, 500, 2.0, None).0);
+            assert!(dz.is_some());
+        }
+    }
+
+    #[test]
+    fn set_from_defaults() {
+        // the set isn't mirrored or calculated with the vectorised kernels, but it has the same
+        // escape iterations as the Mandelbrot set in both f64 and f32
+        for &precision in &[Precision::Double, Precision::Single] {
+            let def = SetDefinition::new(-2.0, 1.0, -1.0, 0.75, 48, 2, 200, 2.0)
+                .with_periodicity(false)
+                .with_interior_check(false)
+                .with_precision(Some(precision));
+            let set_data = calc_set(&def, &Formula, None);
+            assert_eq!(calc_set_parallel(&def, &Formula, 3).data, set_data.data);
+            assert_eq!(calc_set(&def, &Mandelbrot, None).data, set_data.data);
+        }
+    }
+
+    #[test]
+    fn interior_colourings() {
+        for &mode in &[InteriorColouring::Black, InteriorColouring::FinalMagnitude] {
+            assert!(Formula.has_interior(mode));
+        }
+        assert!(!Formula.has_interior(InteriorColouring::Period));
+        assert!(Mandelbrot.has_interior(InteriorColouring::Period));
+    }
+
+    #[test]
+    fn julia_escape() {
+        // for c = 0 the set is the unit disk
+        let disk = Julia { c: Complex::new(0.0, 0.0) };
+        assert_eq!(escape_iterations(&disk, Complex::new(0.6, -0.7), 100, 2.0, None), (0, 0));
+        assert_ne!(escape_iterations(&disk, Complex::new(0.8, 0.7), 100, 2.0, None).0, 0);
+        // the orbit of zero is the orbit of c in the Mandelbrot set a step later
+        for i in 0..100 {
+            let c = Complex::new(-2.0 + 0.0271 * i as f64, 1.1 - 0.0213 * i as f64);
+            let (iterations, _) = escape_iterations(&Julia { c }, Complex::new(0.0, 0.0), 500, 2.0, None);
+            assert_eq!(iterations.saturating_sub(1), precision::escape_iterations(c, 500, 2.0, None).0);
+        }
+    }
+
+    #[test]
+    fn julia_set_parallel() {
+        // the set for a real c is mirrored in the real axis, which gives the same samples
+        let def = SetDefinition::new(-1.5, 1.5, -1.0, 0.5, 48, 2, 200, 2.0)
+            .with_interior(InteriorColouring::FinalMagnitude);
+        for &c in &[Complex::new(-0.75, 0.0), Complex::new(-0.8, 0.156)] {
+            let julia = Julia { c };
+            assert_eq!(julia.symmetric(), c.imag == 0.0);
+            let set_data = calc_set(&def, &julia, None);
+            assert_eq!(calc_set_parallel(&def, &julia, 3).data, set_data.data);
+            assert!(set_data.data.contains(&0));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use complex::Complex;
+
+/// The longest cycle that is searched for when looking for the attracting cycle of a point.
+const MAX_PERIOD: u32 = 1024;
+/// Distance between two orbit points below which they are considered to be the same point
+/// of a cycle. Only needs to be close enough for Newton's method to converge from there.
+const CYCLE_TOLERANCE: f64 = 1e-9;
+/// Maximum number of steps of Newton's method used to refine the cycle.
+const NEWTON_STEPS: u32 = 64;
+
+/// How the points inside the set are coloured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InteriorColouring {
+    /// All interior points are black.
+    Black,
+    /// The estimated distance from the point to the boundary of the set.
+    Distance,
+    /// The magnitude of the multiplier of the attracting cycle, |λ|.
+    Multiplier,
+    /// The period of the attracting cycle.
+    Period,
+    /// The magnitude of `z` after the maximum number of iterations.
+    FinalMagnitude,
+}
+
+impl InteriorColouring {
+    /// Returns the colouring whose name is `name`, or `None` if there isn't one.
+    pub fn from_name(name: &str) -> Option<InteriorColouring> {
+        match name {
+            "black" => Some(InteriorColouring::Black),
+            "distance" => Some(InteriorColouring::Distance),
+            "multiplier" => Some(InteriorColouring::Multiplier),
+            "period" => Some(InteriorColouring::Period),
+            "magnitude" => Some(InteriorColouring::FinalMagnitude),
+            _ => None,
+        }
+    }
+}
+
+/// The derivatives of the `period`th iterate of z² + c at a point on a cycle.
+struct Derivatives {
+    z: Complex,
+    dz: Complex,
+    dc: Complex,
+    dzdz: Complex,
+    dcdz: Complex,
+}
+
+/// Returns the value used to colour the point `c` which is assumed to be inside the set.
+///
+/// For the values that depend on the attracting cycle of `c` the orbit is iterated until it
+/// settles onto the cycle, the period is found by looking for the first return to the
+/// settled point, and the point is refined onto the cycle with Newton's method.
+pub fn interior_value(c: Complex, mode: InteriorColouring, max_iterations: u32) -> f64 {
+    let z = settle(c, max_iterations);
+
+    match mode {
+        InteriorColouring::Black => 0.0,
+        InteriorColouring::FinalMagnitude => z.abs(),
+        InteriorColouring::Period => cycle_period(z, c) as f64,
+        InteriorColouring::Multiplier => {
+            let period = cycle_period(z, c);
+            derivatives(refine_cycle(z, c, period), c, period).dz.abs()
+        }
+        InteriorColouring::Distance => {
+            let period = cycle_period(z, c);
+            distance_estimate(&derivatives(refine_cycle(z, c, period), c, period))
+        }
+    }
+}
+
+/// Returns `z` after iterating z² + c from zero `max_iterations` times.
+fn settle(c: Complex, max_iterations: u32) -> Complex {
+    let mut z = c;
+
+    for _ in 1..max_iterations {
+        z = z * z + c;
+    }
+    z
+}
+
+/// Returns the period of the cycle containing `z`, or the period of the closest return if
+/// the orbit hasn't settled onto the cycle within the tolerance.
+fn cycle_period(z: Complex, c: Complex) -> u32 {
+    let tolerance = CYCLE_TOLERANCE * CYCLE_TOLERANCE;
+    let mut w = z;
+    let mut closest_period = 1;
+    let mut closest_dist = f64::MAX;
+
+    for period in 1..MAX_PERIOD + 1 {
+        w = w * w + c;
+        let dist = (w - z).norm_sqr();
+
+        if dist < tolerance {
+            return period;
+        }
+        if dist < closest_dist {
+            closest_dist = dist;
+            closest_period = period;
+        }
+    }
+    closest_period
+}
+
+/// Uses Newton's method to find the point of the cycle with the given period closest to `z`.
+fn refine_cycle(z: Complex, c: Complex, period: u32) -> Complex {
+    let one = Complex::new(1.0, 0.0);
+    let mut w = z;
+
+    for _ in 0..NEWTON_STEPS {
+        let derivs = derivatives(w, c, period);
+        let step = (derivs.z - w) / (derivs.dz - one);
+        w = w - step;
+
+        if step.norm_sqr() < 1e-30 {
+            break;
+        }
+    }
+    w
+}
+
+/// Calculates the `period`th iterate of z² + c starting from `z` and its derivatives.
+fn derivatives(z: Complex, c: Complex, period: u32) -> Derivatives {
+    let two = Complex::new(2.0, 0.0);
+    let one = Complex::new(1.0, 0.0);
+    let mut d = Derivatives {
+        z,
+        dz: one,
+        dc: Complex::new(0.0, 0.0),
+        dzdz: Complex::new(0.0, 0.0),
+        dcdz: Complex::new(0.0, 0.0),
+    };
+
+    for _ in 0..period {
+        // the order matters, every derivative is calculated from the previous values
+        d.dcdz = two * (d.dz * d.dc + d.z * d.dcdz);
+        d.dzdz = two * (d.dz * d.dz + d.z * d.dzdz);
+        d.dc = two * d.z * d.dc + one;
+        d.dz = two * d.z * d.dz;
+        d.z = d.z * d.z + c;
+    }
+    d
+}
+
+/// The interior distance estimate (1 - |∂z|²) / |∂c∂z + ∂z∂z ∂c / (1 - ∂z)|.
+fn distance_estimate(d: &Derivatives) -> f64 {
+    let one = Complex::new(1.0, 0.0);
+    (1.0 - d.dz.norm_sqr()) / (d.dcdz + d.dzdz * d.dc / (one - d.dz)).abs()
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_of_main_cardioid() {
+        let value = interior_value(Complex::new(0.1, 0.1), InteriorColouring::Period, 1000);
+        assert_eq!(value, 1.0);
+    }
+
+    #[test]
+    fn period_of_period_2_bulb() {
+        let value = interior_value(Complex::new(-1.0, 0.1), InteriorColouring::Period, 1000);
+        assert_eq!(value, 2.0);
+    }
+
+    #[test]
+    fn period_of_period_3_bulb() {
+        // the centre of the period 3 bulb at the top of the main cardioid
+        let c = Complex::new(-0.122561, 0.744862);
+        assert_eq!(interior_value(c, InteriorColouring::Period, 1000), 3.0);
+    }
+
+    #[test]
+    fn multiplier_at_centre_of_cardioid() {
+        // zero is the superattracting fixed point of z², the multiplier is zero
+        let value = interior_value(Complex::new(0.0, 0.0), InteriorColouring::Multiplier, 1000);
+        assert!(value < 1e-12);
+    }
+
+    #[test]
+    fn multiplier_of_fixed_point() {
+        // the fixed point of z² + c is (1 - sqrt(1 - 4c)) / 2 and the multiplier is 2z
+        let c = Complex::new(0.2, 0.0);
+        let z = (1.0 - (1.0 - 4.0 * 0.2f64).sqrt()) / 2.0;
+        let value = interior_value(c, InteriorColouring::Multiplier, 1000);
+        assert!((value - 2.0 * z).abs() < 1e-12);
+    }
+
+    #[test]
+    fn distance_shrinks_towards_boundary() {
+        // the cardioid crosses the positive real axis at 0.25
+        let near = interior_value(Complex::new(0.24, 0.0), InteriorColouring::Distance, 1000);
+        let far = interior_value(Complex::new(0.0, 0.0), InteriorColouring::Distance, 1000);
+        assert!(near > 0.0);
+        assert!(near < far);
+        // the estimate is within a factor of 4 of the true distance
+        assert!(near > 0.01 / 4.0 && near < 0.01 * 4.0);
+    }
+
+    #[test]
+    fn final_magnitude() {
+        // the fixed point for c = -0.5 is (1 - sqrt(3)) / 2
+        let value = interior_value(Complex::new(-0.5, 0.0), InteriorColouring::FinalMagnitude, 1000);
+        assert!((value - (3.0f64.sqrt() - 1.0) / 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn from_name() {
+        assert_eq!(InteriorColouring::from_name("period"), Some(InteriorColouring::Period));
+        assert_eq!(InteriorColouring::from_name("foo"), None);
+    }
+}
@@ -4,52 +4,230 @@ extern crate env_logger;
 extern crate time;
 #[macro_use]
 extern crate bmp;
-extern crate threadpool;
+extern crate png;
+extern crate tiff;
 
 mod complex;
 mod colour;
 mod vector3d;
+mod interior;
+mod options;
+mod shading;
+mod trap;
+mod exterior;
+mod average;
+mod simd;
+mod subdivision;
+mod balance;
+mod adaptive;
+mod pattern;
+mod stream;
+mod bands;
+mod double_double;
+mod fixed;
+mod floatexp;
+mod precision;
+mod perturbation;
+mod series;
+mod fractal;
 
-use threadpool::ThreadPool;
 //use std::sync::mpsc::channel;
 use complex::Complex;
 use std::vec::Vec;
 use bmp::Image;
 use colour::Colour;
-use std::sync::mpsc;
-use std::error::Error;
+use interior::InteriorColouring;
+use options::Options;
+use shading::Lighting;
+use trap::OrbitTrap;
+use exterior::ExteriorColouring;
+use average::Average;
+use subdivision::Subdivision;
+use adaptive::AdaptiveSampling;
+use pattern::SamplePattern;
+use bands::ImageFormat;
+use double_double::DdComplex;
+use fixed::FixedComplex;
+use floatexp::{FeComplex, FloatExp};
+use precision::{Float, Precision};
+use perturbation::{References, ReferenceOrbit};
+use fractal::{Fractal, Julia, Mandelbrot};
+use std::process;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::mem;
+use std::thread;
 
 fn main() {
     env_logger::init().unwrap();
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        }
+    };
     let start_time = time::precise_time_s();
 //    let set_def = SetDefinition::new(-0.77, -0.74, 0.07, 0.11, 1200, 2, 400, 10.0);
-    let set_def = SetDefinition::new(-0.77, -0.76, 0.09, 0.10, 1200, 4, 400, 10.0);
+    let set_def = SetDefinition::new(-0.77, -0.76, 0.09, 0.10, 1200, 4, 400, 10.0)
+        .with_interior(options.interior)
+        .with_lighting(options.lighting)
+        .with_exterior(options.exterior, options.trap)
+        .with_field_lines(options.field_lines)
+        .with_interior_check(options.interior_check)
+        .with_periodicity(options.periodicity)
+        .with_subdivision(options.subdivision)
+        .with_symmetry(options.symmetry)
+        .with_cost_estimate(options.cost_estimate)
+        .with_adaptive(options.adaptive)
+        .with_pattern(options.pattern, options.seed)
+        .with_view(options.centre, options.px_size)
+        .with_precision(options.precision)
+        .with_perturbation(options.perturbation)
+        .with_series_approximation(options.series_approximation);
+    // the whole of a Julia set is shown unless the view is given
+    let set_def = match options.julia {
+        Some(_) if options.centre.is_none() && options.px_size.is_none() => {
+            let centre = FixedComplex::from_complex(Complex::new(0.0, 0.0));
+            set_def.with_view(Some(centre), Some(FloatExp::from_f64(JULIA_WIDTH / set_def.width_px as f64)))
+        }
+        _ => set_def,
+    };
 //    let set_def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 1200, 2, 100, 10.0);
     info!("set_def = {:?}", set_def);
-    // TODO use std::env::args() or getopts to specify the number of threads?
-    let set_data = calc_set_parallel(&set_def, 8);
-//    let set_data = calc_set(&set_def);
-    info!("time taken to calculate set {:.*}ms", 2, (time::precise_time_s() - start_time) * 1000f64);
-    info!("set_data size = {}", set_data.data.len());
-    let img = render(&set_data);
-    let _ = img.save("/Users/chris/tmp/mandelbrot.bmp");
+    info!("precision = {:?}, perturbation = {}", set_def.precision(), set_def.uses_perturbation());
+    info!("escape iterations kernel lanes = {} (f64), {} (f32)", simd::lanes(), simd::single_lanes());
+    let texture = match options.texture {
+        Some(ref path) => match bmp::open(path) {
+            Ok(texture) => Some(texture),
+            Err(err) => {
+                eprintln!("Failed to open texture '{}': {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let format = ImageFormat::from_path(&options.output, set_def.width_px, set_def.height_px);
+
+    let result = match options.julia {
+        Some(c) => {
+            info!("rendering the Julia set for c = {:?}", c);
+            render_set(&set_def, &Julia { c }, &options, texture, format, start_time)
+        }
+        None => render_set(&set_def, &Mandelbrot, &options, texture, format, start_time),
+    };
+    if let Err(msg) = result {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+/// The width of the default view of a Julia set, which covers the Julia sets of every constant
+/// in the Mandelbrot set as they lie inside the circle of radius 2.
+const JULIA_WIDTH: f64 = 4.0;
+
+/// Calculates the set defined by `set_def` for `fractal` and writes the image to the output
+/// file given by the options.
+fn render_set<F: Fractal>(
+    set_def: &SetDefinition,
+    fractal: &F,
+    options: &Options,
+    texture: Option<Image>,
+    format: ImageFormat,
+    start_time: f64,
+) -> Result<(), String> {
+    if !fractal.has_interior(set_def.interior) {
+        return Err(format!("The interior of the fractal can't be coloured by {:?}", set_def.interior));
+    }
+    let precision = set_def.precision();
+    if !fractal.is_mandelbrot() && !matches!(precision, Precision::Single | Precision::Double) {
+        return Err(format!("Only the Mandelbrot set can be calculated in {:?} precision", precision));
+    }
+    // neighbouring samples have the same nearest f64 point in the higher precisions
+    if let (Precision::DoubleDouble | Precision::Fixed, Some(option)) = (precision, set_def.f64_colouring()) {
+        return Err(format!("The colouring given by {} can't be calculated in {:?} precision", option, precision));
+    }
+
+    if options.sample_buffer {
+        let set_data = calc_set_parallel(set_def, fractal, options.threads);
+//        let set_data = calc_set(set_def, fractal, None);
+        info!("time taken to calculate set {:.*}ms", 2, (time::precise_time_s() - start_time) * 1000f64);
+        info!("set_data size = {}", set_data.data.len());
+
+        if let Some(ref periods) = set_data.periods {
+            info!("cycles detected for {} samples", periods.iter().filter(|&&period| period != 0).count());
+        }
+        bands::save_image(&render(&set_data, texture.as_ref()), &options.output, format)
+    } else if format == ImageFormat::Bmp {
+        let pixel_data = stream::calc_pixels_parallel(set_def, fractal, options.threads, texture);
+        info!("time taken to calculate set {:.*}ms", 2, (time::precise_time_s() - start_time) * 1000f64);
+        bands::save_image(&pixel_data.image(), &options.output, format)
+    } else {
+        let memory_limit = options.memory_limit.checked_mul(1 << 20)
+            .ok_or_else(|| format!("The memory limit of {}MB is too large", options.memory_limit))?;
+        let result = bands::render_bands(set_def, fractal, options.threads, texture, &options.output, format, memory_limit);
+        info!("time taken to render set {:.*}ms", 2, (time::precise_time_s() - start_time) * 1000f64);
+        result
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 struct SetDefinition {
     origin: Complex,
-    px_size: f64,
+    /// The origin in the full precision it was given in, which the samples are offset from
+    /// when they're calculated in more than f64 precision. `origin` is the nearest f64 to it.
+    exact_origin: FixedComplex,
+    /// The index of the pixel at the origin in the grid of pixels covering the plane, which the
+    /// random sample positions are generated from.
+    origin_px: (i64, i64),
+    /// The width of each pixel, which can be far smaller than an f64 can hold.
+    px_size: FloatExp,
     width_px: u32,
     height_px: u32,
     oversampling: u32,
     max_iterations: u32,
     escape_radius: f64,
+    interior: InteriorColouring,
+    lighting: Option<Lighting>,
+    exterior: ExteriorColouring,
+    trap: OrbitTrap,
+    field_lines: bool,
+    /// Whether points inside the main cardioid and the period-2 bulb are detected before
+    /// iterating them. This only needs to be turned off for benchmarking.
+    interior_check: bool,
+    /// Whether the orbits of points are checked for cycles so points attracted to a cycle can
+    /// be identified as in the set without iterating them up to the maximum iterations.
+    periodicity: bool,
+    subdivision: Subdivision,
+    /// Whether samples are mirrored in the real axis instead of being calculated when the
+    /// view straddles it.
+    symmetry: bool,
+    /// Whether the cost of calculating each region is estimated before the set is calculated
+    /// in parallel, so the work can be shared evenly between the threads.
+    cost_estimate: bool,
+    /// The thresholds for supersampling pixels adaptively, or `None` if every pixel is
+    /// supersampled. When sampling adaptively the oversampling is the maximum for each pixel.
+    adaptive: Option<AdaptiveSampling>,
+    /// The positions of the samples within each pixel.
+    pattern: SamplePattern,
+    /// The seed for the random sample positions.
+    seed: u64,
+    /// The precision the samples are calculated in, or `None` if it's chosen from the distance
+    /// between samples.
+    precision: Option<Precision>,
+    /// Whether the samples are calculated relative to reference orbits when their precision is
+    /// higher than f64, so only the reference orbits need the higher precision.
+    perturbation: bool,
+    /// Whether the first iterations of the samples are skipped using a series approximation
+    /// when they're calculated relative to reference orbits.
+    series_approximation: bool,
 }
 
 /// Definition which specifies how to calculate the Mandelbrot Set for an area of
 /// the complex plane.
 impl SetDefinition {
     // TODO Replace this with a builder
+    #[allow(clippy::too_many_arguments)]
     fn new(
         min_real: f64,
         max_real: f64,
@@ -66,202 +244,1095 @@ impl SetDefinition {
         let px_size = (max_real - min_real) / (width_px as f64);
         let height_px = (max_imag - min_imag) / px_size;
 
+        let origin = Complex::new(min_real, min_imag);
+
         SetDefinition {
-            origin: Complex::new(min_real, min_imag),
-            px_size,
+            origin,
+            exact_origin: FixedComplex::from_complex(origin),
+            origin_px: pattern::pixel_index(DdComplex::from_complex(origin), px_size),
+            px_size: FloatExp::from_f64(px_size),
             width_px,
             height_px: (height_px as u32),
             oversampling,
             max_iterations,
             escape_radius,
+            interior: InteriorColouring::Black,
+            lighting: None,
+            exterior: ExteriorColouring::Iterations,
+            trap: OrbitTrap::Point { centre: Complex::new(0.0, 0.0) },
+            field_lines: false,
+            interior_check: true,
+            periodicity: true,
+            subdivision: Subdivision::Off,
+            symmetry: true,
+            cost_estimate: false,
+            adaptive: None,
+            pattern: SamplePattern::Grid,
+            seed: 0,
+            precision: None,
+            perturbation: true,
+            series_approximation: true,
+        }
+    }
+
+    /// Returns a copy of this definition which colours the interior of the set using `interior`.
+    fn with_interior(self, interior: InteriorColouring) -> SetDefinition {
+        SetDefinition { interior, ..self }
+    }
+
+    /// Returns a copy of this definition which shades the outside of the set using `lighting`,
+    /// or doesn't shade it if `lighting` is `None`.
+    fn with_lighting(self, lighting: Option<Lighting>) -> SetDefinition {
+        SetDefinition { lighting, ..self }
+    }
+
+    /// Returns a copy of this definition which colours the outside of the set using `exterior`
+    /// with `trap` as the orbit trap for the colourings that use one.
+    fn with_exterior(self, exterior: ExteriorColouring, trap: OrbitTrap) -> SetDefinition {
+        SetDefinition { exterior, trap, ..self }
+    }
+
+    /// Returns a copy of this definition which draws field lines over the outside of the set
+    /// if `field_lines` is true.
+    fn with_field_lines(self, field_lines: bool) -> SetDefinition {
+        SetDefinition { field_lines, ..self }
+    }
+
+    /// Returns a copy of this definition which checks whether points are inside the main
+    /// cardioid or the period-2 bulb before iterating them if `interior_check` is true.
+    fn with_interior_check(self, interior_check: bool) -> SetDefinition {
+        SetDefinition { interior_check, ..self }
+    }
+
+    /// Returns a copy of this definition which checks the orbits of points for cycles if
+    /// `periodicity` is true.
+    fn with_periodicity(self, periodicity: bool) -> SetDefinition {
+        SetDefinition { periodicity, ..self }
+    }
+
+    /// Returns a copy of this definition which uses `subdivision` to avoid calculating the
+    /// samples in uniform regions.
+    fn with_subdivision(self, subdivision: Subdivision) -> SetDefinition {
+        SetDefinition { subdivision, ..self }
+    }
+
+    /// Returns a copy of this definition which mirrors samples in the real axis if `symmetry`
+    /// is true.
+    fn with_symmetry(self, symmetry: bool) -> SetDefinition {
+        SetDefinition { symmetry, ..self }
+    }
+
+    /// Returns a copy of this definition which estimates the cost of calculating each region
+    /// before calculating it in parallel if `cost_estimate` is true.
+    fn with_cost_estimate(self, cost_estimate: bool) -> SetDefinition {
+        SetDefinition { cost_estimate, ..self }
+    }
+
+    /// Returns a copy of this definition which supersamples pixels adaptively using the
+    /// thresholds in `adaptive`, or supersamples every pixel if `adaptive` is `None`.
+    fn with_adaptive(self, adaptive: Option<AdaptiveSampling>) -> SetDefinition {
+        SetDefinition { adaptive, ..self }
+    }
+
+    /// Returns a copy of this definition which positions the samples in each pixel using
+    /// `pattern`, with the random positions generated from `seed`.
+    fn with_pattern(self, pattern: SamplePattern, seed: u64) -> SetDefinition {
+        SetDefinition { pattern, seed, ..self }
+    }
+
+    /// Returns a copy of this definition which calculates the samples in `precision`, or in
+    /// the precision chosen from the distance between samples if `precision` is `None`.
+    fn with_precision(self, precision: Option<Precision>) -> SetDefinition {
+        SetDefinition { precision, ..self }
+    }
+
+    /// Returns a copy of this definition which calculates the samples relative to reference
+    /// orbits in deep zooms if `perturbation` is true.
+    fn with_perturbation(self, perturbation: bool) -> SetDefinition {
+        SetDefinition { perturbation, ..self }
+    }
+
+    /// Returns a copy of this definition which skips the first iterations of the samples using
+    /// a series approximation when they're calculated relative to reference orbits if
+    /// `series_approximation` is true.
+    fn with_series_approximation(self, series_approximation: bool) -> SetDefinition {
+        SetDefinition { series_approximation, ..self }
+    }
+
+    /// Returns a copy of this definition with the same size in pixels, centred on `centre`
+    /// with pixels `px_size` wide. The current centre or pixel size is kept if either is
+    /// `None`. The centre can have as many digits as the pixel size needs, so views can be
+    /// far deeper than f64 can position.
+    fn with_view(self, centre: Option<FixedComplex>, px_size: Option<FloatExp>) -> SetDefinition {
+        if centre.is_none() && px_size.is_none() {
+            return self;
+        }
+        let half_size = |px_size: FloatExp| FeComplex::new(px_size * (self.width_px as f64) * 0.5, px_size * (self.height_px as f64) * 0.5);
+        let centre = centre.unwrap_or_else(|| self.exact_origin + half_size(self.px_size));
+        let px_size = px_size.unwrap_or(self.px_size);
+        let exact_origin = centre + half_size(-px_size);
+        let origin_px = pattern::pixel_index(exact_origin.to_dd(), px_size.to_f64());
+        SetDefinition { origin: exact_origin.to_complex(), exact_origin, origin_px, px_size, ..self }
+    }
+
+    /// Returns the precision the samples are calculated in. Unless it's been set, this is the
+    /// lowest precision which can position the samples accurately.
+    fn precision(&self) -> Precision {
+        self.precision.unwrap_or_else(|| {
+            let far_corner = self.origin + self.pixel_offset(self.width_px as usize, self.height_px as usize).to_complex();
+            let magnitude = [self.origin.real, self.origin.imag, far_corner.real, far_corner.imag].iter()
+                .fold(0.0, |max: f64, coord| max.max(coord.abs()));
+            Precision::for_spacing(self.sample_size().to_f64(), magnitude, self.max_iterations)
+        })
+    }
+
+    /// Returns a copy of this definition whose precision is the one chosen for the whole set,
+    /// so the tiles and previews it's split into are calculated in the same precision rather
+    /// than one chosen from their own extents.
+    fn with_resolved_precision(&self) -> SetDefinition {
+        self.with_precision(Some(self.precision()))
+    }
+
+    /// Returns the option of the first colouring used by the definition whose values are
+    /// calculated from the nearest f64 point by `calc_sample`, or `None` if there isn't one and
+    /// the samples are only coloured by their escape iterations.
+    fn f64_colouring(&self) -> Option<&'static str> {
+        if self.interior != InteriorColouring::Black {
+            Some("--interior")
+        } else if self.exterior != ExteriorColouring::Iterations {
+            Some("--exterior")
+        } else if self.lighting.is_some() {
+            Some("--light")
+        } else if self.field_lines {
+            Some("--field-lines")
+        } else {
+            None
         }
     }
 
-    /// Splits this definition into multiple definitions covering the same area,
-    /// allowing them to be processed in parallel and assembled into a single image
-    /// during rendering.
+    /// Returns true if the samples are calculated relative to reference orbits.
+    fn uses_perturbation(&self) -> bool {
+        self.perturbation && matches!(self.precision(), Precision::DoubleDouble | Precision::Fixed)
+    }
+
+    /// Returns the origin in double-double precision.
+    fn origin_dd(&self) -> DdComplex {
+        self.exact_origin.to_dd()
+    }
+
+    /// Returns the distance between samples without the sample pattern, which is the pixel size
+    /// divided by the oversampling.
+    fn sample_size(&self) -> FloatExp {
+        self.px_size / FloatExp::from_f64(self.oversampling as f64)
+    }
+
+    /// Returns the offset from the origin of the point `x` and `y` pixels from it, which can be
+    /// fractions of a pixel.
+    fn px_offset(&self, x: f64, y: f64) -> FeComplex {
+        FeComplex::new(self.px_size * x, self.px_size * y)
+    }
+
+    /// Returns the offset from the origin of the bottom left corner of the pixel at `x` and `y`.
+    fn pixel_offset(&self, x: usize, y: usize) -> FeComplex {
+        self.px_offset(x as f64, y as f64)
+    }
+
+    /// Returns the index of the pixel at `x` and `y` in the grid of pixels covering the plane.
+    fn pixel_index(&self, x: usize, y: usize) -> (i64, i64) {
+        (self.origin_px.0.wrapping_add(x as i64), self.origin_px.1.wrapping_add(y as i64))
+    }
+
+    /// Returns a copy of this definition with its origin moved by `x_px` and `y_px` pixels. The
+    /// origin is moved in full precision so it stays accurate however many times it's moved.
+    fn offset_px(&self, x_px: u32, y_px: u32) -> SetDefinition {
+        let exact_origin = self.exact_origin + self.pixel_offset(x_px as usize, y_px as usize);
+        let origin_px = self.pixel_index(x_px as usize, y_px as usize);
+        SetDefinition { origin: exact_origin.to_complex(), exact_origin, origin_px, ..*self }
+    }
+
+    /// Returns true if all the values calculated for a sample are the same as the values for
+    /// the sample's mirror image in the real axis. The lighting and the colourings that depend
+    /// on the argument of the orbit points or on the orbit trap aren't symmetric, and neither
+    /// are the sample patterns other than the regular grid.
+    fn is_symmetric(&self) -> bool {
+        let exterior_symmetric = matches!(self.exterior,
+            ExteriorColouring::Iterations |
+            ExteriorColouring::Average(Average::TriangleInequality) |
+            ExteriorColouring::Average(Average::Curvature));
+        let pattern_symmetric = self.pattern == SamplePattern::Grid && self.adaptive.is_none();
+        self.symmetry && self.lighting.is_none() && pattern_symmetric && exterior_symmetric
+    }
+
+    /// Returns a copy of this definition aligned with the real axis, along with the sum of
+    /// the indices of each pair of sample rows which are mirror images of each other, or
+    /// `None` if there are no such rows or the definition isn't symmetric.
     ///
-    /// The area is split into evenly sized horizontal strips. Each area in the returned vector
-    /// has the same width as the input and has a height approximately equal to the height of
-    /// the input divided by `count`.
-    fn split(&self, count: u32) -> Vec<SetDefinition> {
-        let mut heights = vec![self.height_px / count; count as usize];
-        let rem = self.height_px % count;
+    /// The origin is moved by up to a quarter of the distance between samples so the real
+    /// axis lies on a row of samples or exactly halfway between two rows.
+    fn mirrored_rows(&self) -> Option<(SetDefinition, u32)> {
+        if !self.is_symmetric() {
+            return None;
+        }
+        let sample_size = self.sample_size();
+        let rows = self.height_px * self.oversampling;
+        let sum = (self.exact_origin.imag.to_floatexp() * -2.0 / sample_size).to_f64().round();
 
-        for i in 0..rem {
-            heights[i as usize] += 1;
+        // there must be at least one pair of different rows in the definition
+        if sum < 1.0 || sum > (2 * rows) as f64 - 3.0 {
+            return None;
         }
-        let mut imag = self.origin.imag;
-        let mut defs: Vec<SetDefinition> = Vec::with_capacity(count as usize);
+        let imag = sample_size * (-sum / 2.0);
+        let origin = Complex::new(self.origin.real, imag.to_f64());
+        let exact_origin = FixedComplex::new(self.exact_origin.real, fixed::Fixed::from_floatexp(imag));
+        Some((SetDefinition { origin, exact_origin, ..*self }, sum as u32))
+    }
+
+    /// Returns the distance within which an orbit point must return to an earlier point for
+    /// the orbit to be considered a cycle. This is a small fraction of the distance between
+    /// samples so it's much smaller than any detail visible in the image.
+    fn periodicity_tolerance(&self) -> f64 {
+        self.sample_size().to_f64() * PERIODICITY_TOLERANCE
+    }
+
+    /// Returns the number of samples in the set, including the oversampling.
+    fn sample_count(&self) -> usize {
+        (self.width_px * self.height_px * self.oversampling * self.oversampling) as usize
+    }
+
+    /// Splits this definition into square tiles covering the same area, allowing them to be
+    /// processed in parallel and written into the set data for the whole area.
+    ///
+    /// The tiles are `size` pixels wide and high, apart from the tiles on the right and top
+    /// edges which are cut down to fit inside the area. They are returned by row, starting
+    /// from the bottom left.
+    fn tiles(&self, size: u32) -> Vec<Tile> {
+        let mut tiles = Vec::new();
 
-        for i in 0..count {
-            let origin = Complex::new(self.origin.real, imag);
-            let height = heights[i as usize];
-            let def = SetDefinition { origin, height_px: height, ..*self };
-            defs.push(def);
-            imag += height as f64 * self.px_size;
+        for y_px in (0..self.height_px).step_by(size as usize) {
+            for x_px in (0..self.width_px).step_by(size as usize) {
+                tiles.push(self.tile(x_px, y_px, size.min(self.width_px - x_px), size.min(self.height_px - y_px)));
+            }
         }
-        defs
+        tiles
+    }
+
+    /// Returns the tile of this definition with its bottom left corner at `x_px` and `y_px`.
+    fn tile(&self, x_px: u32, y_px: u32, width_px: u32, height_px: u32) -> Tile {
+        let def = self.offset_px(x_px, y_px);
+        Tile { x_px, y_px, def: SetDefinition { width_px, height_px, ..def } }
     }
 }
 
+/// A part of a set definition calculated by one thread, along with its position in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tile {
+    x_px: u32,
+    y_px: u32,
+    def: SetDefinition,
+}
+
+/// The periodicity tolerance as a fraction of the distance between samples.
+const PERIODICITY_TOLERANCE: f64 = 1e-4;
+
 struct SetData {
     def: SetDefinition,
     data: Vec<u32>,
+    /// The value used to colour each sample in the set, only calculated if the interior
+    /// isn't coloured black. The values of samples outside the set are meaningless.
+    interior: Option<Vec<f64>>,
+    /// The factor applied to the colour of each sample outside the set, only calculated if
+    /// the definition has lighting or field lines.
+    shading: Option<Vec<f64>>,
+    /// The value used to colour each sample outside the set, only calculated if the exterior
+    /// isn't coloured by iterations. The values of samples inside the set are meaningless.
+    exterior: Option<Vec<Complex>>,
+    /// The period of the cycle found in the orbit of each sample, only calculated if the
+    /// definition checks for periodicity. Zero if no cycle was found, which includes the
+    /// samples skipped by the interior check.
+    periods: Option<Vec<u32>>,
+}
+
+impl SetData {
+    /// Creates empty set data for `def` with enough capacity for `capacity` samples.
+    fn new(def: &SetDefinition, capacity: usize) -> SetData {
+        fn layer<T>(enabled: bool, capacity: usize) -> Option<Vec<T>> {
+            if enabled { Some(Vec::with_capacity(capacity)) } else { None }
+        }
+        SetData {
+            def: *def,
+            data: Vec::with_capacity(capacity),
+            interior: layer(def.interior != InteriorColouring::Black, capacity),
+            shading: layer(def.lighting.is_some() || def.field_lines, capacity),
+            exterior: layer(def.exterior != ExteriorColouring::Iterations, capacity),
+            periods: layer(def.periodicity, capacity),
+        }
+    }
+
+    /// Appends the samples in `row` of `other` to the samples in this set data.
+    fn append_row(&mut self, other: &SetData, row: usize) {
+        let row_len = (other.def.width_px * other.def.oversampling) as usize;
+        let range = row * row_len..(row + 1) * row_len;
+        self.data.extend_from_slice(&other.data[range.clone()]);
+        extend_layer(&mut self.interior, &other.interior, range.clone());
+        extend_layer(&mut self.shading, &other.shading, range.clone());
+        extend_layer(&mut self.exterior, &other.exterior, range.clone());
+        extend_layer(&mut self.periods, &other.periods, range);
+    }
+
+    /// Creates set data for `def` with every sample set to zero, ready to have tiles written
+    /// into it.
+    fn filled(def: &SetDefinition) -> SetData {
+        let len = def.sample_count();
+        let mut set_data = SetData::new(def, len);
+        set_data.data.resize(len, 0);
+        resize_layer(&mut set_data.interior, len, 0.0);
+        resize_layer(&mut set_data.shading, len, 0.0);
+        resize_layer(&mut set_data.exterior, len, Complex::new(0.0, 0.0));
+        resize_layer(&mut set_data.periods, len, 0);
+        set_data
+    }
+
+    /// Removes the samples and changes the definition to `def`, which must have the same layers,
+    /// keeping the memory allocated for the samples.
+    fn reset(&mut self, def: &SetDefinition) {
+        self.def = *def;
+        self.data.clear();
+        clear_layer(&mut self.interior);
+        clear_layer(&mut self.shading);
+        clear_layer(&mut self.exterior);
+        clear_layer(&mut self.periods);
+    }
+
+    /// Returns the sample at `idx`. The values in the layers that weren't calculated are zero,
+    /// apart from the shade which is one.
+    fn sample(&self, idx: usize) -> Sample {
+        Sample {
+            iterations: self.data[idx],
+            interior: self.interior.as_ref().map_or(0.0, |values| values[idx]),
+            shade: self.shading.as_ref().map_or(1.0, |values| values[idx]),
+            exterior: self.exterior.as_ref().map_or(Complex::new(0.0, 0.0), |values| values[idx]),
+            period: self.periods.as_ref().map_or(0, |values| values[idx]),
+        }
+    }
+}
+
+/// Storage for the samples of a set, which are added in order by row starting from the bottom
+/// left.
+trait Samples {
+    /// Adds a sample after the samples already added.
+    fn push(&mut self, sample: Sample);
+
+    /// Returns the sample at `idx`, which must already have been added.
+    fn get(&self, idx: usize) -> Sample;
+}
+
+impl Samples for SetData {
+    fn push(&mut self, sample: Sample) {
+        self.data.push(sample.iterations);
+        push_layer(&mut self.interior, sample.interior);
+        push_layer(&mut self.shading, sample.shade);
+        push_layer(&mut self.exterior, sample.exterior);
+        push_layer(&mut self.periods, sample.period);
+    }
+
+    fn get(&self, idx: usize) -> Sample {
+        self.sample(idx)
+    }
+}
+
+/// The destination of the tiles of samples calculated in parallel.
+///
+/// The output is split into a part for each tile before the tiles are calculated, so the
+/// threads write their tiles into their own parts without sharing the output.
+trait TileWriter {
+    /// The part of the output a tile is written to.
+    type Part<'a>: Send where Self: 'a;
+
+    /// Splits the output into the parts the tiles are written to, in the same order as the
+    /// tiles, which must not overlap.
+    fn split(&mut self, tiles: &[Tile]) -> Vec<Self::Part<'_>>;
+
+    /// Calculates a tile using `calculator` and writes it to its part of the output.
+    fn write_tile<F: Fractal>(part: Self::Part<'_>, tile: &Tile, calculator: &mut TileCalculator<F>);
+}
+
+/// The samples of a tile, written straight into the rows of the set data of the whole set.
+struct TileSamples<'a> {
+    /// The number of samples in each row of the tile.
+    row_len: usize,
+    /// The number of samples added so far.
+    len: usize,
+    data: Vec<&'a mut [u32]>,
+    interior: Option<Vec<&'a mut [f64]>>,
+    shading: Option<Vec<&'a mut [f64]>>,
+    exterior: Option<Vec<&'a mut [Complex]>>,
+    periods: Option<Vec<&'a mut [u32]>>,
+}
+
+impl Samples for TileSamples<'_> {
+    fn push(&mut self, sample: Sample) {
+        let (row, col) = (self.len / self.row_len, self.len % self.row_len);
+        self.data[row][col] = sample.iterations;
+        set_layer(&mut self.interior, row, col, sample.interior);
+        set_layer(&mut self.shading, row, col, sample.shade);
+        set_layer(&mut self.exterior, row, col, sample.exterior);
+        set_layer(&mut self.periods, row, col, sample.period);
+        self.len += 1;
+    }
+
+    fn get(&self, idx: usize) -> Sample {
+        let (row, col) = (idx / self.row_len, idx % self.row_len);
+        Sample {
+            iterations: self.data[row][col],
+            interior: self.interior.as_ref().map_or(0.0, |rows| rows[row][col]),
+            shade: self.shading.as_ref().map_or(1.0, |rows| rows[row][col]),
+            exterior: self.exterior.as_ref().map_or(Complex::new(0.0, 0.0), |rows| rows[row][col]),
+            period: self.periods.as_ref().map_or(0, |rows| rows[row][col]),
+        }
+    }
+}
+
+impl TileWriter for SetData {
+    type Part<'a> = TileSamples<'a>;
+
+    fn split(&mut self, tiles: &[Tile]) -> Vec<TileSamples<'_>> {
+        let os = self.def.oversampling as usize;
+        let row_len = self.def.width_px as usize * os;
+        let rects = tiles.iter()
+            .map(|tile| (tile.x_px as usize * os, tile.y_px as usize * os, tile.def.width_px as usize * os, tile.def.height_px as usize * os))
+            .collect::<Vec<_>>();
+        let mut data = split_rects(&mut self.data, row_len, &rects).into_iter();
+        let mut interior = self.interior.as_mut().map(|layer| split_rects(layer, row_len, &rects).into_iter());
+        let mut shading = self.shading.as_mut().map(|layer| split_rects(layer, row_len, &rects).into_iter());
+        let mut exterior = self.exterior.as_mut().map(|layer| split_rects(layer, row_len, &rects).into_iter());
+        let mut periods = self.periods.as_mut().map(|layer| split_rects(layer, row_len, &rects).into_iter());
+
+        rects.iter().map(|&(_, _, width, _)| TileSamples {
+            row_len: width,
+            len: 0,
+            data: data.next().unwrap(),
+            interior: interior.as_mut().and_then(Iterator::next),
+            shading: shading.as_mut().and_then(Iterator::next),
+            exterior: exterior.as_mut().and_then(Iterator::next),
+            periods: periods.as_mut().and_then(Iterator::next),
+        }).collect()
+    }
+
+    /// Calculates the samples of a tile straight into the set data.
+    fn write_tile<F: Fractal>(mut part: TileSamples<'_>, tile: &Tile, calculator: &mut TileCalculator<F>) {
+        calculator.calc_into(&tile.def, &mut part);
+    }
+}
+
+/// Splits a buffer of rows `row_len` long into the rows of rectangles, each given by the
+/// column and row of its first sample followed by its width and height. The rectangles must
+/// not overlap. The rows of each rectangle are returned in order starting from its first row.
+fn split_rects<'a, T>(buffer: &'a mut [T], row_len: usize, rects: &[(usize, usize, usize, usize)]) -> Vec<Vec<&'a mut [T]>> {
+    // the rows of every rectangle, in the order they're found in the buffer
+    let mut segments = rects.iter().enumerate()
+        .flat_map(|(idx, &(x, y, width, height))| (y..y + height).map(move |row| (row, x, width, idx)))
+        .collect::<Vec<_>>();
+    segments.sort_unstable();
+    let mut segments = segments.into_iter().peekable();
+    let mut parts = rects.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+
+    for (row, mut rest) in buffer.chunks_mut(row_len).enumerate() {
+        let mut start = 0;
+
+        while let Some(&(_, x, width, idx)) = segments.peek().filter(|segment| segment.0 == row) {
+            let (segment, tail) = mem::take(&mut rest)[x - start..].split_at_mut(width);
+            parts[idx].push(segment);
+            rest = tail;
+            start = x + width;
+            segments.next();
+        }
+    }
+    parts
+}
+
+fn push_layer<T>(layer: &mut Option<Vec<T>>, value: T) {
+    if let Some(ref mut layer) = *layer {
+        layer.push(value);
+    }
+}
+
+fn extend_layer<T: Copy>(layer: &mut Option<Vec<T>>, other: &Option<Vec<T>>, range: Range<usize>) {
+    if let (Some(layer), Some(other)) = (layer.as_mut(), other.as_ref()) {
+        layer.extend_from_slice(&other[range]);
+    }
+}
+
+fn clear_layer<T>(layer: &mut Option<Vec<T>>) {
+    if let Some(ref mut layer) = *layer {
+        layer.clear();
+    }
+}
+
+fn resize_layer<T: Clone>(layer: &mut Option<Vec<T>>, len: usize, value: T) {
+    if let Some(ref mut layer) = *layer {
+        layer.resize(len, value);
+    }
+}
+
+fn set_layer<T>(layer: &mut Option<Vec<&mut [T]>>, row: usize, col: usize, value: T) {
+    if let Some(ref mut rows) = *layer {
+        rows[row][col] = value;
+    }
+}
+
+/// The values calculated for a single sample point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sample {
+    /// The escape iterations, zero if the point is in the set.
+    iterations: u32,
+    /// The interior colouring value, zero if the point isn't in the set.
+    interior: f64,
+    /// The lighting and field line factor, one if the point is in the set.
+    shade: f64,
+    /// The exterior colouring value, zero if the point is in the set.
+    exterior: Complex,
+    /// The period of the cycle found in the orbit, zero if no cycle was found.
+    period: u32,
 }
 
 //--------------------------------------------------------------------------------------------------
 
-/// Returns the number of iterations it takes the point's magnitude to exceed the
-/// escape radius. Zero is returned if the point is in the set.
-fn escape_iterations(point: Complex, max_iterations: u32, escape_radius: f64) -> u32 {
-    let escape_value = escape_radius * escape_radius;
-    let mut z = point;
-
-    for i in 0..max_iterations {
-        // it's more efficient to explode the complex into real and imaginary parts rather
-        // than multiplying the Complex. this way the squares only need to be calculated once
-        // and the square root can be avoided altogether
-        let zr2 = z.real * z.real;
-        let zi2 = z.imag * z.imag;
-        let zri = z.real * z.imag;
-
-        if zr2 + zi2 > escape_value {
-            return i;
-        }
-        z = Complex::new(zr2 - zi2 + point.real, zri + zri + point.imag);
-    }
-    0
-}
-
-//fn escape_iterations_simd(point1: Complex,
-//                          point2: Complex,
-//                          max_iterations: u32,
-//                          escape_radius: f64) -> (u32, u32) {
-//
-//    let escape_value = f64x2::splat(escape_radius * escape_radius);
-//    let mut real = f64x2::new(point1.real, point2.real);
-//    let mut imag = f64x2::new(point1.imag, point2.imag);
-//    let mut iter_count = u64x2::splat(0.0);
-//
-//    for _ in 0..max_iterations {
-//        // it's more efficient to explode the complex into real and imaginary parts rather
-//        // than multiplying the Complex. this way the squares only need to be calculated once
-//        // and the square root can be avoided altogether
-//        let zr2 = real * real;
-//        let zi2 = imag * imag;
-//        let zri = real * imag;
-//        let mask = (zr2 + zi2).gt(escape_value);
-//
-//        if mask.all() {
-//            return
-//        }
-//        z = Complex::new(zr2 - zi2 + point.real, zri + zri + point.imag);
-//    }
-//    0
-//}
-
-/// Calculates a set in parallel using the thread pool.
-fn calc_set_parallel(set_def: &SetDefinition, threads: u32) -> SetData {
-    let thread_pool = ThreadPool::new(threads as usize);
-    let (tx, rx) = mpsc::channel();
-    // TODO What multiplier?
-    let defs = set_def.split(threads * 10);
-    let size = defs.len();
-
-    for (idx, def) in defs.into_iter().enumerate() {
-        let tx_clone = tx.clone();
-        thread_pool.execute(move || {
-            let set_data = calc_set(&def);
-            // send back a tuple with the index and the calculated set data
-            // the index allows the sets to be assembled in the correct order to create an image
-            tx_clone.send((idx, set_data)).unwrap();
-        });
+/// Calculates a set in parallel on `threads` threads. Every tile is calculated in the precision
+/// chosen for the whole set.
+///
+/// If the set is symmetric and straddles the real axis only the rows on one side of the axis
+/// and the rows without a mirror image are calculated, and the other rows are mirrored from
+/// them. The rows are calculated in whole pixels, so a few rows of samples may be calculated
+/// on both sides of the axis.
+fn calc_set_parallel<F: Fractal>(set_def: &SetDefinition, fractal: &F, threads: u32) -> SetData {
+    let set_def = &set_def.with_resolved_precision();
+    let mirrored = if fractal.symmetric() { set_def.mirrored_rows() } else { None };
+    let (aligned, sum) = match mirrored {
+        Some(mirrored) => mirrored,
+        None => {
+            let reference = reference_orbit(set_def, fractal);
+            return calc_tiles_parallel(set_def, fractal, reference.as_ref(), threads, SetData::filled(set_def));
+        }
+    };
+    let os = aligned.oversampling;
+    let rows = aligned.height_px * os;
+    // calculate the rows on the side of the axis where the rows without mirror images are
+    let (first_px, end_px) = if sum < rows {
+        (sum.div_ceil(2) / os, aligned.height_px)
+    } else {
+        (0, (sum / 2 / os + 1).min(aligned.height_px))
+    };
+    let calculated_def = SetDefinition {
+        height_px: end_px - first_px,
+        ..aligned.offset_px(0, first_px)
+    };
+    let reference = reference_orbit(&calculated_def, fractal);
+    let calculated = calc_tiles_parallel(&calculated_def, fractal, reference.as_ref(), threads, SetData::filled(&calculated_def));
+    let mut set_data = SetData::new(&aligned, aligned.sample_count());
+
+    for row in 0..rows {
+        let source = if row >= first_px * os && row < end_px * os { row } else { sum - row };
+        set_data.append_row(&calculated, (source - first_px * os) as usize);
+    }
+    set_data
+}
+
+/// Calculates a set in parallel, splitting it into tiles which the threads take from a shared
+/// queue and write to their parts of `output`, which is returned once all the tiles have been
+/// written. The samples are calculated relative to `reference` if it isn't `None`, which is
+/// shared by all the tiles.
+fn calc_tiles_parallel<F, W>(set_def: &SetDefinition, fractal: &F, reference: Option<&ReferenceOrbit>, threads: u32, mut output: W) -> W
+    where F: Fractal, W: TileWriter
+{
+    let tiles = if set_def.cost_estimate {
+        let start_time = time::precise_time_s();
+        let tiles = balance::balanced_tiles(set_def, fractal, threads);
+        info!("time taken to estimate costs {:.*}ms", 2, (time::precise_time_s() - start_time) * 1000f64);
+        tiles
+    } else {
+        set_def.tiles(TILE_SIZE)
+    };
+    info!("calculating {} tiles", tiles.len());
+    // the queue of tiles along with the parts of the output they're written to, which is
+    // dropped once the threads have finished so the output can be returned
+    let queue = Mutex::new(tiles.iter().zip(output.split(&tiles)));
+
+    thread::scope(|scope| {
+        let queue = &queue;
+        let handles = (0..threads).map(|thread| scope.spawn(move || {
+            let start_time = time::precise_time_s();
+            let mut stats = ThreadStats { thread, tiles: 0, samples: 0, time: 0.0 };
+            let mut calculator = TileCalculator { fractal, reference, buffer: None };
+
+            // keep taking tiles from the queue until there are none left, so the threads that get
+            // the quicker tiles calculate more of them
+            loop {
+                let next = queue.lock().unwrap().next();
+                let (tile, part) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                W::write_tile(part, tile, &mut calculator);
+                stats.tiles += 1;
+                stats.samples += tile.def.sample_count();
+            }
+            stats.time = time::precise_time_s() - start_time;
+            stats
+        })).collect::<Vec<_>>();
+
+        for handle in handles {
+            let stats = handle.join().unwrap();
+            info!("thread {} calculated {} tiles with {} samples in {:.*}ms",
+                stats.thread, stats.tiles, stats.samples, 2, stats.time * 1000f64);
+        }
+    });
+    drop(queue);
+    output
+}
+
+/// Calculates the tiles taken by one thread, keeping a buffer for the tiles whose samples are
+/// stored before they're written which is reused for each tile.
+struct TileCalculator<'a, F: 'a> {
+    fractal: &'a F,
+    reference: Option<&'a ReferenceOrbit>,
+    buffer: Option<SetData>,
+}
+
+impl<F: Fractal> TileCalculator<'_, F> {
+    /// Calculates the samples of the set defined by `def` and adds them to `samples`.
+    fn calc_into<S: Samples>(&self, def: &SetDefinition, samples: &mut S) {
+        calc_samples(def, self.fractal, self.reference, samples);
+    }
+
+    /// Calculates the set defined by `def` into the buffer and returns it.
+    fn calc(&mut self, def: &SetDefinition) -> &SetData {
+        let mut buffer = match self.buffer.take() {
+            Some(mut buffer) => {
+                buffer.reset(def);
+                buffer
+            }
+            None => SetData::new(def, def.sample_count()),
+        };
+        self.calc_into(def, &mut buffer);
+        self.buffer.insert(buffer)
+    }
+}
+
+/// The width and height of the tiles a set is split into for calculating in parallel, in pixels.
+const TILE_SIZE: u32 = 32;
+
+/// The work done by one thread while calculating a set in parallel.
+struct ThreadStats {
+    thread: u32,
+    tiles: u32,
+    samples: usize,
+    /// The time in seconds between the thread starting and running out of tiles.
+    time: f64,
+}
+
+/// Calculates the set defined by `set_def` for `fractal` in a single thread, with the samples
+/// calculated relative to `reference` if it isn't `None`.
+#[cfg(test)]
+fn calc_set<F: Fractal>(set_def: &SetDefinition, fractal: &F, reference: Option<&ReferenceOrbit>) -> SetData {
+    let mut set_data = SetData::new(set_def, set_def.sample_count());
+    calc_samples(set_def, fractal, reference, &mut set_data);
+    set_data
+}
+
+/// Calculates the samples of the set defined by `set_def` for `fractal` like `calc_set`, and
+/// adds them to `samples`.
+fn calc_samples<F: Fractal, S: Samples>(set_def: &SetDefinition, fractal: &F, reference: Option<&ReferenceOrbit>, samples: &mut S) {
+    if let Some(ref adaptive) = set_def.adaptive {
+        adaptive::calc_samples(set_def, fractal, adaptive, reference, samples);
+        return;
+    }
+    let width = (set_def.width_px * set_def.oversampling) as usize;
+    let height = (set_def.height_px * set_def.oversampling) as usize;
+    let sample_size = set_def.sample_size();
+    let os = set_def.oversampling as usize;
+    // the samples are given by their offsets from the origin, which are small enough to be
+    // accurate with an f64 mantissa at any zoom
+    let offset = |x: usize, y: usize| {
+        if set_def.pattern == SamplePattern::Grid {
+            return FeComplex::new(sample_size * (x as f64), sample_size * (y as f64));
+        }
+        let pixel = set_def.pixel_index(x / os, y / os);
+        let (dx, dy) = set_def.pattern.position(pixel, (y % os) * os + x % os, os, set_def.seed);
+        set_def.pixel_offset(x / os, y / os) + set_def.px_offset(dx, dy)
+    };
+    let mut references = reference.map(References::new);
+    // the escape iterations of all the samples are calculated in batches, using the vectorised
+    // kernels for the Mandelbrot set, and the other values are only calculated for the samples
+    // that need them
+    let (iterations, periods, sources) = subdivision::subdivide(width, height, set_def.subdivision, offset, |offsets, iterations, periods| {
+        batch_escape(set_def, fractal, references.as_mut(), offsets, iterations, periods)
+    });
+
+    let copy_filled = set_def.f64_colouring().is_none();
+
+    for (idx, (&iters, &period)) in iterations.iter().zip(&periods).enumerate() {
+        // the samples filled by subdivision are copies of the samples they were filled from,
+        // unless their colourings vary within the filled rectangle
+        let sample = match sources[idx] {
+            source if source != idx && copy_filled => samples.get(source),
+            _ => calc_sample(set_def, fractal, set_def.origin + offset(idx % width, idx / width).to_complex(), iters, period),
+        };
+        samples.push(sample);
+    }
+}
+
+/// Returns the reference orbit shared by the samples of a set, or `None` if they aren't
+/// calculated relative to one. Only the Mandelbrot set has a perturbed kernel.
+fn reference_orbit<F: Fractal>(set_def: &SetDefinition, fractal: &F) -> Option<ReferenceOrbit> {
+    if fractal.is_mandelbrot() {
+        ReferenceOrbit::primary(set_def)
+    } else {
+        None
+    }
+}
+
+/// Calculates the escape iterations of a batch of points given by their offsets from the
+/// origin of the definition for `fractal`, in the definition's precision, along with the
+/// periods of the cycles found if the definition checks for periodicity.
+///
+/// The Mandelbrot set is calculated with its own kernels, relative to `references` if it isn't
+/// `None`. The points of the other formulas are iterated one at a time in f32 or f64, which
+/// are the only precisions they can be calculated in.
+fn batch_escape<F: Fractal>(
+    set_def: &SetDefinition,
+    fractal: &F,
+    references: Option<&mut References>,
+    offsets: &[FeComplex],
+    iterations: &mut [u32],
+    periods: &mut [u32],
+) {
+    if fractal.is_mandelbrot() {
+        return batch_escape_offsets(set_def, references, offsets, iterations, periods);
+    }
+    let points = offsets.iter().map(|&offset| set_def.origin + offset.to_complex());
+
+    match set_def.precision() {
+        Precision::Single => escape_formula_points(set_def, fractal, points.map(Complex::<f32>::from_complex), iterations, periods),
+        Precision::Double => escape_formula_points(set_def, fractal, points, iterations, periods),
+        precision => panic!("Only the Mandelbrot set can be calculated in {:?} precision", precision),
+    }
+}
+
+/// Calculates the escape iterations and periods of the points of a formula one at a time in
+/// the precision of `T`.
+fn escape_formula_points<F, T, P>(set_def: &SetDefinition, fractal: &F, points: P, iterations: &mut [u32], periods: &mut [u32])
+    where F: Fractal, T: Float, P: Iterator<Item = Complex<T>> {
+    let tolerance = if set_def.periodicity { Some(set_def.periodicity_tolerance()) } else { None };
+
+    for (point, (iters, period)) in points.zip(iterations.iter_mut().zip(periods.iter_mut())) {
+        (*iters, *period) = if set_def.interior_check && fractal.in_known_interior(point.to_complex()) {
+            (0, 0)
+        } else {
+            fractal::escape_iterations(fractal, point, set_def.max_iterations, set_def.escape_radius, tolerance)
+        };
+    }
+}
+
+/// Calculates the escape iterations of a batch of points of the Mandelbrot set like
+/// `batch_escape`.
+///
+/// If there are reference orbits the points are calculated relative to them. Otherwise in f32 and
+/// f64 the points are calculated using the vectorised kernels, and in higher precisions each
+/// point is iterated on its own.
+fn batch_escape_offsets(
+    set_def: &SetDefinition,
+    references: Option<&mut References>,
+    offsets: &[FeComplex],
+    iterations: &mut [u32],
+    periods: &mut [u32],
+) {
+    if let Some(references) = references {
+        return perturbation::batch_escape_perturbed(set_def, references, offsets, iterations, periods);
+    }
+    match set_def.precision() {
+        Precision::Single | Precision::Double => {
+            let points = offsets.iter().map(|&offset| set_def.origin + offset.to_complex()).collect::<Vec<_>>();
+            batch_escape_iterations(set_def, &points, iterations, periods);
+        }
+        Precision::DoubleDouble => {
+            let origin = set_def.origin_dd();
+            let points = offsets.iter().map(|&offset| origin + offset.to_complex());
+            escape_precise_points(set_def, points, iterations, periods);
+        }
+        Precision::Fixed => {
+            let limbs = fixed::limbs_for_spacing(set_def.sample_size());
+            let origin = set_def.exact_origin.with_limbs(limbs);
+            let points = offsets.iter().map(|&offset| (origin + offset).with_limbs(limbs)).map(|point| Complex::new(point.real, point.imag));
+            escape_precise_points(set_def, points, iterations, periods);
+        }
     }
-    // vector containing pairs of (index, SetData), each element is one slice of the whole set
-    let mut sets: Vec<(usize, SetData)> = Vec::with_capacity(size);
+}
 
-    // fill up the vector with values sent over channels from the threads calculating the sets
-    while sets.len() < size {
-        match rx.recv() {
-            Ok(data) => sets.push(data),
-            Err(err) => panic!("Received error '{}'", err.description()),
+/// Calculates the escape iterations and periods of points whose real and imaginary parts are
+/// given in a precision higher than f64. The interior check is made in the same precision
+/// because deep zooms are close to the boundaries of the cardioid and the bulb.
+fn escape_precise_points<T, P>(set_def: &SetDefinition, points: P, iterations: &mut [u32], periods: &mut [u32])
+    where T: precision::Real, P: Iterator<Item = Complex<T>> {
+    let tolerance = if set_def.periodicity { Some(set_def.periodicity_tolerance()) } else { None };
+
+    for (point, (iters, period)) in points.zip(iterations.iter_mut().zip(periods.iter_mut())) {
+        (*iters, *period) = if set_def.interior_check && precision::in_cardioid_or_bulb(point) {
+            (0, 0)
+        } else {
+            precision::escape_iterations(point, set_def.max_iterations, set_def.escape_radius, tolerance)
         };
     }
-    // sort the sets by index so the strips are in the correct order before rendering
-    sets.sort_by(|&(idx1, _), &(idx2, _)| idx1.cmp(&idx2));
-    // create a vector containing only the set data, not the indices
-    let mut data_vec = sets.into_iter().map(|(_, set_data)| set_data.data).collect::<Vec<_>>();
-    let capacity = set_def.width_px * set_def.height_px;
-    // create a vector to hold the data for the entire set
-    let mut data = Vec::with_capacity(capacity as usize);
+}
+
+/// Calculates the escape iterations of a batch of points using the vectorised kernel for the
+/// definition's precision, which is f32 or f64, along with the periods of the cycles found if
+/// the definition checks for periodicity.
+///
+/// If the definition's interior check is enabled the points inside the main cardioid and the
+/// period-2 bulb are removed from the batch before it's passed to the kernel.
+fn batch_escape_iterations(set_def: &SetDefinition, points: &[Complex], iterations: &mut [u32], periods: &mut [u32]) {
+    let single = set_def.precision() == Precision::Single;
+    let kernel = |points: &[Complex], iterations: &mut [u32], periods: &mut [u32]| {
+        let (max_iterations, escape_radius) = (set_def.max_iterations, set_def.escape_radius);
+        let tolerance = set_def.periodicity_tolerance();
+
+        match (single, set_def.periodicity) {
+            (true, true) => simd::escape_iterations_row_single_periodic(points, max_iterations, escape_radius, tolerance, iterations, periods),
+            (true, false) => simd::escape_iterations_row_single(points, max_iterations, escape_radius, iterations),
+            (false, true) => simd::escape_iterations_row_periodic(points, max_iterations, escape_radius, tolerance, iterations, periods),
+            (false, false) => simd::escape_iterations_row(points, max_iterations, escape_radius, iterations),
+        }
+    };
+    for period in periods.iter_mut() {
+        *period = 0;
+    }
+    if !set_def.interior_check {
+        kernel(points, iterations, periods);
+        return;
+    }
+    let outside = (0..points.len()).filter(|&idx| !precision::in_cardioid_or_bulb(points[idx])).collect::<Vec<_>>();
+    let outside_points = outside.iter().map(|&idx| points[idx]).collect::<Vec<_>>();
+    let mut outside_iterations = vec![0; outside.len()];
+    let mut outside_periods = vec![0; outside.len()];
+    kernel(&outside_points, &mut outside_iterations, &mut outside_periods);
 
-    for v in data_vec.iter_mut() {
-        data.append(v);
+    for iters in iterations.iter_mut() {
+        *iters = 0;
+    }
+    for (&idx, (&iters, &period)) in outside.iter().zip(outside_iterations.iter().zip(&outside_periods)) {
+        iterations[idx] = iters;
+        periods[idx] = period;
     }
-    SetData { def: *set_def, data }
 }
 
-/// Calculates the set defined by `set_def`.
-fn calc_set(set_def: &SetDefinition) -> SetData {
-    let capacity = set_def.width_px * set_def.height_px * set_def.oversampling * set_def.oversampling;
-    let mut point_data: Vec<u32> = Vec::with_capacity(capacity as usize);
-    let px_size = set_def.px_size / (set_def.oversampling as f64);
+/// Calculates the values of the sample at `point` needed to colour it, given its escape
+/// iterations and the period of its cycle.
+///
+/// The lighting and field lines need the final value of z and its derivative, so escaping
+/// points are iterated again to find them if the definition uses either. These values and the
+/// interior and exterior values are always calculated in f64, so the sets calculated in a
+/// higher precision can't use them.
+fn calc_sample<F: Fractal>(set_def: &SetDefinition, fractal: &F, point: Complex, iterations: u32, period: u32) -> Sample {
+    let mut shade = 1.0;
+
+    if iterations != 0 && (set_def.lighting.is_some() || set_def.field_lines) {
+        let (_, z, dz) = fractal::escape_orbit(fractal, point, set_def.max_iterations, set_def.escape_radius);
 
-    for i in 0..set_def.height_px * set_def.oversampling {
-        for r in 0..set_def.width_px * set_def.oversampling {
-            let point = set_def.origin + Complex::new((r as f64) * px_size, (i as f64) * px_size);
-            let escape_iters = escape_iterations(point, set_def.max_iterations, set_def.escape_radius);
-            point_data.push(escape_iters);
+        if let (Some(lighting), Some(dz)) = (&set_def.lighting, dz) {
+            shade *= lighting.shade(z, dz);
+        }
+        if set_def.field_lines {
+            shade *= exterior::field_line_factor(z, set_def.escape_radius);
         }
     }
-    SetData { def: *set_def, data: point_data }
+    let interior = if iterations == 0 && set_def.interior != InteriorColouring::Black {
+        fractal.interior_value(point, set_def.interior, set_def.max_iterations)
+    } else {
+        0.0
+    };
+    let exterior = if iterations != 0 && set_def.exterior != ExteriorColouring::Iterations {
+        exterior::exterior_value(fractal, point, set_def.exterior, &set_def.trap, set_def.max_iterations, set_def.escape_radius)
+    } else {
+        Complex::new(0.0, 0.0)
+    };
+    Sample { iterations, interior, shade, exterior, period }
 }
 
 /// Renders Mandelbrot Set data into an image.
-fn render(set: &SetData) -> Image {
+///
+/// `texture` is the image mapped onto the set by the image orbit trap colouring.
+fn render(set: &SetData, texture: Option<&Image>) -> Image {
     let mut img = Image::new(set.def.width_px, set.def.height_px);
-    // TODO This needs to handle set data calculated in parallel
-    let (min_iter, max_iter) = escape_iter_range(&set.data);
-    info!("(min_iter, max_iter) = ({}, {})", min_iter, max_iter);
-    // TODO Need to create a fixed, larger number of colours and smooth between iterations.
-    let num_colours = max_iter - min_iter + 1;
-    debug!("num_colours = {}", num_colours);
-    let palette_vertices = vec![
-        Colour::from_24bit_int(0x010d62),
-        Colour::from_24bit_int(0x63b8ec),
-        Colour::from_24bit_int(0xffffff),
-        Colour::from_24bit_int(0xffb700),
-        Colour::from_24bit_int(0x611012),
-    ];
-    let colours = colour::palette(num_colours, &palette_vertices);
-    debug!("colours.len() = {}", colours.len());
+    let colouring = Colouring::new(set, texture.cloned());
+    let sample_colour = |idx: usize| colouring.colour(&set.sample(idx));
 
     for (x, y) in img.coordinates() {
         let real_idx = x;
         // need to reverse the y co-ordinate because the image origin is top left
         let imag_idx = set.def.height_px - y - 1;
         let clr = colour::pixel_colour(
-            &set.data,
             real_idx,
             imag_idx,
             set.def.width_px,
             set.def.oversampling,
-            min_iter,
-            &colours,
+            &sample_colour,
         );
         img.set_pixel(x, y, clr.pixel());
     }
     img
 }
 
-fn escape_iter_range(set_vec: &Vec<u32>) -> (u32, u32) {
+/// The palettes and value ranges used to colour the samples of a set.
+struct Colouring {
+    def: SetDefinition,
+    min_iter: u32,
+    max_iter: u32,
+    colours: Vec<Colour>,
+    interior_range: Option<(f64, f64)>,
+    exterior_range: Option<(f64, f64)>,
+    interior_colours: Vec<Colour>,
+    exterior_colours: Vec<Colour>,
+    /// The image mapped onto the set by the image orbit trap colouring.
+    texture: Option<Image>,
+}
+
+impl Colouring {
+    /// Creates the colouring for a set, spreading the palettes over the ranges of the values
+    /// in `set`.
+    ///
+    /// The set data only needs to be representative of the set, so the colouring can be
+    /// created from a low resolution version of it. Values outside its ranges are given the
+    /// colours at the ends of the palettes.
+    fn new(set: &SetData, texture: Option<Image>) -> Colouring {
+        // TODO This needs to handle set data calculated in parallel
+        let (min_iter, max_iter) = escape_iter_range(&set.data);
+        info!("(min_iter, max_iter) = ({}, {})", min_iter, max_iter);
+        // TODO Need to create a fixed, larger number of colours and smooth between iterations.
+        let num_colours = max_iter - min_iter + 1;
+        debug!("num_colours = {}", num_colours);
+        let palette_vertices = vec![
+            Colour::from_24bit_int(0x010d62),
+            Colour::from_24bit_int(0x63b8ec),
+            Colour::from_24bit_int(0xffffff),
+            Colour::from_24bit_int(0xffb700),
+            Colour::from_24bit_int(0x611012),
+        ];
+        let colours = colour::palette(num_colours, &palette_vertices);
+        debug!("colours.len() = {}", colours.len());
+
+        let interior_range = set.interior.as_ref().and_then(|values| {
+            let inside = set.data.iter().zip(values).filter(|&(&iters, _)| iters == 0);
+            value_range(inside.map(|(_, &value)| scale_interior_value(set.def.interior, value)))
+        });
+        let exterior_range = set.exterior.as_ref().and_then(|values| {
+            let outside = set.data.iter().zip(values).filter(|&(&iters, _)| iters != 0);
+            value_range(outside.map(|(_, value)| value.real))
+        });
+        Colouring {
+            def: set.def,
+            min_iter,
+            max_iter,
+            colours,
+            interior_range,
+            exterior_range,
+            interior_colours: colour::palette(INTERIOR_COLOURS, &palette_vertices),
+            exterior_colours: colour::palette(EXTERIOR_COLOURS, &palette_vertices),
+            texture,
+        }
+    }
+
+    /// Returns this colouring with the outside of the set shaded like the samples of `def`,
+    /// for colouring a set whose ranges were found from samples without the shading.
+    fn with_shading(self, def: &SetDefinition) -> Colouring {
+        Colouring {
+            def: SetDefinition { lighting: def.lighting, field_lines: def.field_lines, ..self.def },
+            ..self
+        }
+    }
+
+    /// Returns the colour of a sample.
+    fn colour(&self, sample: &Sample) -> Colour {
+        let col = self.unshaded_colour(sample);
+
+        // shade the outside of the set by scaling the colour by the lighting and field line factor
+        if sample.iterations != 0 && (self.def.lighting.is_some() || self.def.field_lines) {
+            Colour::from_vector3d(&(col.to_vector3d() * sample.shade))
+        } else {
+            col
+        }
+    }
+
+    fn unshaded_colour(&self, sample: &Sample) -> Colour {
+        // use the number of iterations to look up the colour in the palette
+        // the palette has the right number of colours so each number of iterations
+        // is rendered in a different colour
+        let iteration_colour = |iters: u32| self.colours[(iters.clamp(self.min_iter, self.max_iter) - self.min_iter) as usize];
+        let iters = sample.iterations;
+
+        if iters != 0 {
+            match (self.def.exterior, self.exterior_range) {
+                (ExteriorColouring::Iterations, _) => iteration_colour(iters),
+                (ExteriorColouring::TrapImage, _) => match self.texture {
+                    Some(ref texture) if !sample.exterior.real.is_nan() => texture_colour(texture, sample.exterior),
+                    _ => iteration_colour(iters),
+                },
+                (_, Some((min, max))) =>
+                    colour::palette_colour(&self.exterior_colours, (sample.exterior.real - min) / (max - min)),
+                _ => iteration_colour(iters),
+            }
+        } else if let Some((min, max)) = self.interior_range {
+            let value = scale_interior_value(self.def.interior, sample.interior);
+            colour::palette_colour(&self.interior_colours, (value - min) / (max - min))
+        } else {
+            colour::BLACK
+        }
+    }
+}
+
+/// Number of colours in the palette used for the interior of the set.
+const INTERIOR_COLOURS: u32 = 256;
+/// Number of colours in the palette used for the outside of the set when it isn't coloured
+/// by iterations.
+const EXTERIOR_COLOURS: u32 = 256;
+
+/// Converts an interior colouring value into the value that is mapped linearly onto the palette.
+///
+/// Distances are spread over several orders of magnitude so they are coloured by their logarithm.
+fn scale_interior_value(interior: InteriorColouring, value: f64) -> f64 {
+    match interior {
+        InteriorColouring::Distance => value.max(f64::MIN_POSITIVE).ln(),
+        _ => value,
+    }
+}
+
+/// Returns the minimum and maximum of the finite values, or `None` if there aren't any.
+fn value_range<I>(values: I) -> Option<(f64, f64)> where I: Iterator<Item = f64> {
+    let mut range: Option<(f64, f64)> = None;
+
+    for value in values.filter(|value| value.is_finite()) {
+        range = match range {
+            None => Some((value, value)),
+            Some((min, max)) => Some((min.min(value), max.max(value))),
+        };
+    }
+    range
+}
+
+/// Returns the colour of the texture at the texture coordinates, each of which is in the
+/// range 0 to 1 with the origin at the bottom left of the texture.
+fn texture_colour(texture: &Image, coords: Complex) -> Colour {
+    let (width, height) = (texture.get_width(), texture.get_height());
+    let x = ((coords.real * width as f64) as u32).min(width - 1);
+    // the image origin is top left
+    let y = height - 1 - ((coords.imag * height as f64) as u32).min(height - 1);
+    Colour::from_pixel(&texture.get_pixel(x, y))
+}
+
+fn escape_iter_range(set_vec: &[u32]) -> (u32, u32) {
     let mut min = set_vec[0];
     let mut max = set_vec[0];
 
-    for i in 1..set_vec.len() {
-        let val = set_vec[i];
-
+    for &val in set_vec.iter().skip(1) {
         if val > max {
             max = val;
         }
@@ -278,43 +1349,319 @@ fn escape_iter_range(set_vec: &Vec<u32>) -> (u32, u32) {
 mod tests {
     use super::SetDefinition;
     use complex::Complex;
+    use interior::InteriorColouring;
+    use exterior::ExteriorColouring;
+    use trap::OrbitTrap;
+    use shading::Lighting;
+    use subdivision::Subdivision;
+    use adaptive::AdaptiveSampling;
+    use pattern::SamplePattern;
+    use precision::{self, Precision};
+    use perturbation::ReferenceOrbit;
+    use fractal::{self, Mandelbrot};
+    use fixed::FixedComplex;
+    use floatexp::FloatExp;
 
     #[test]
-    fn split_simple() {
-        let def = SetDefinition {
-            origin: Complex::new(1.0, 2.0),
-            px_size: 0.01,
-            width_px: 200,
-            height_px: 100,
-            oversampling: 2,
-            max_iterations: 100,
-            escape_radius: 2.0,
-        };
-        let expected = vec![
-            SetDefinition { origin: Complex::new(1.0, 2.0), height_px: 25, ..def },
-            SetDefinition { origin: Complex::new(1.0, 2.25), height_px: 25, ..def },
-            SetDefinition { origin: Complex::new(1.0, 2.5), height_px: 25, ..def },
-            SetDefinition { origin: Complex::new(1.0, 2.75), height_px: 25, ..def },
-        ];
-        assert_eq!(def.split(4), expected);
+    fn escape_orbit_matches_escape_iterations() {
+        for &(real, imag) in &[(0.3, 0.5), (-0.75, 0.1), (-2.0, 1.0), (0.0, 0.0), (-0.7615, 0.0955)] {
+            let point = Complex::new(real, imag);
+            let (iterations, _, _) = fractal::escape_orbit(&Mandelbrot, point, 500, 10.0);
+            assert_eq!(iterations, precision::escape_iterations(point, 500, 10.0, None).0);
+        }
+    }
+
+    #[test]
+    fn in_cardioid_or_bulb() {
+        assert!(precision::in_cardioid_or_bulb(Complex::new(0.0, 0.0)));
+        assert!(precision::in_cardioid_or_bulb(Complex::new(0.24, 0.0)));
+        assert!(precision::in_cardioid_or_bulb(Complex::new(-0.74, 0.0)));
+        assert!(precision::in_cardioid_or_bulb(Complex::new(-1.2, 0.0)));
+        assert!(precision::in_cardioid_or_bulb(Complex::new(-0.1, 0.64)));
+        assert!(!precision::in_cardioid_or_bulb(Complex::new(0.26, 0.0)));
+        assert!(!precision::in_cardioid_or_bulb(Complex::new(-1.3, 0.0)));
+        // inside the period-3 bulb but not the cardioid
+        assert!(!precision::in_cardioid_or_bulb(Complex::new(-0.122561, 0.744862)));
+    }
+
+    #[test]
+    fn interior_check_gives_same_results() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 120, 2, 200, 2.0);
+        let without = super::calc_set(&def.with_interior_check(false), &Mandelbrot, None);
+        let with = super::calc_set(&def.with_interior_check(true), &Mandelbrot, None);
+        assert_eq!(with.data, without.data);
+        let lit = def.with_lighting(Some(Lighting::new(45.0, 45.0)));
+        assert_eq!(super::calc_set(&lit.with_interior_check(true), &Mandelbrot, None).data, without.data);
+        assert_eq!(super::calc_set(&lit.with_interior_check(false), &Mandelbrot, None).data, without.data);
+    }
+
+    #[test]
+    fn escape_iterations_periodic() {
+        // the centres of the period 1, 2, 3 and 4 components and points just inside them
+        for &(real, imag, period) in &[(0.0, 0.0, 1), (0.2, 0.1, 1), (-1.0, 0.0, 2), (-1.1, 0.05, 2),
+            (-0.122561, 0.744862, 3), (-1.754878, 0.0, 3), (-1.310702, 0.0, 4), (0.282271, 0.530061, 4)] {
+            let point = Complex::new(real, imag);
+            assert_eq!(precision::escape_iterations(point, 10000, 2.0, Some(1e-10)), (0, period));
+        }
+        for &(real, imag) in &[(0.5, 0.5), (-2.0, 1.0), (-0.75, 0.1), (0.26, 0.0)] {
+            let point = Complex::new(real, imag);
+            assert_eq!(precision::escape_iterations(point, 10000, 2.0, Some(1e-10)),
+                (precision::escape_iterations(point, 10000, 2.0, None).0, 0));
+        }
+    }
+
+    #[test]
+    fn periodicity_gives_same_results() {
+        // includes the boundaries of the period-3 bulbs and the tip of the antenna
+        for def in &[SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 150, 2, 1000, 2.0),
+            SetDefinition::new(-0.3, 0.1, 0.5, 0.9, 100, 2, 2000, 2.0),
+            SetDefinition::new(-0.77, -0.76, 0.09, 0.10, 100, 2, 1000, 10.0)] {
+            let without = super::calc_set(&def.with_periodicity(false), &Mandelbrot, None);
+            let with = super::calc_set(&def.with_periodicity(true), &Mandelbrot, None);
+            assert_eq!(with.data, without.data);
+            assert!(without.periods.is_none());
+
+            let periods = with.periods.unwrap();
+            assert!(periods.iter().zip(&with.data).all(|(&period, &iters)| period == 0 || iters == 0));
+            assert!(periods.iter().any(|&period| period != 0));
+        }
+    }
+
+    #[test]
+    fn subdivision_gives_same_results() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 150, 2, 200, 2.0)
+            .with_interior(InteriorColouring::Period)
+            .with_exterior(ExteriorColouring::TrapDistance, OrbitTrap::Point { centre: Complex::new(0.0, 0.0) });
+        let off = super::calc_set(&def, &Mandelbrot, None);
+        let conservative = super::calc_set(&def.with_subdivision(Subdivision::Conservative), &Mandelbrot, None);
+        assert_eq!(conservative.data, off.data);
+        // the colourings vary within the filled rectangles, so they're calculated for every sample
+        assert_eq!(conservative.interior, off.interior);
+        assert_eq!(conservative.exterior, off.exterior);
+    }
+
+    #[test]
+    fn split_rects() {
+        // a buffer of 4 rows of 5 split into rectangles which don't cover all of it
+        let mut buffer = vec![0; 20];
+        let rects = [(0, 0, 2, 3), (2, 0, 3, 1), (3, 2, 2, 2), (0, 3, 1, 1)];
+
+        for (idx, rows) in super::split_rects(&mut buffer, 5, &rects).into_iter().enumerate() {
+            assert_eq!(rows.len(), rects[idx].3);
+
+            for row in rows {
+                assert_eq!(row.len(), rects[idx].2);
+                row.iter_mut().for_each(|value| *value = idx + 1);
+            }
+        }
+        assert_eq!(buffer, vec![1, 1, 2, 2, 2, 1, 1, 0, 0, 0, 1, 1, 0, 3, 3, 4, 0, 0, 3, 3]);
     }
 
     #[test]
-    fn split_with_remainder() {
-        let def = SetDefinition {
-            origin: Complex::new(1.0, 2.0),
-            px_size: 0.01,
-            width_px: 200,
-            height_px: 100,
-            oversampling: 2,
-            max_iterations: 100,
-            escape_radius: 2.0,
+    fn mirrored_rows() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 150, 2, 200, 2.0);
+        let (aligned, sum) = def.mirrored_rows().unwrap();
+        assert_eq!(sum, 200);
+        assert_eq!(aligned.origin.imag, -1.0);
+        // the real axis is three quarters of the way between two rows of samples
+        let (aligned, sum) = SetDefinition::new(-2.0, 1.0, -1.005, 1.0, 150, 2, 200, 2.0).mirrored_rows().unwrap();
+        assert_eq!(sum, 201);
+        assert!((aligned.origin.imag + 1.005).abs() <= 0.0025);
+        assert_eq!(SetDefinition::new(-2.0, 1.0, 0.1, 1.0, 150, 2, 200, 2.0).mirrored_rows(), None);
+        assert_eq!(def.with_lighting(Some(Lighting::new(45.0, 45.0))).mirrored_rows(), None);
+        assert_eq!(def.with_symmetry(false).mirrored_rows(), None);
+    }
+
+    #[test]
+    fn symmetry_gives_same_results() {
+        // the sample positions are exact binary fractions so the mirrored samples are exactly
+        // the same as the calculated ones, with the axis above and below the middle of the view
+        for &(min_imag, max_imag) in &[(-1.0, 0.5), (-0.5, 1.0), (-0.5, 0.75)] {
+            let def = SetDefinition::new(-2.0, 1.0, min_imag, max_imag, 192, 2, 200, 2.0)
+                .with_interior(InteriorColouring::Period);
+            assert!(def.mirrored_rows().is_some());
+            let without = super::calc_set_parallel(&def.with_symmetry(false), &Mandelbrot, 3);
+            let with = super::calc_set_parallel(&def, &Mandelbrot, 3);
+            assert_eq!(with.data, without.data);
+            assert_eq!(with.interior, without.interior);
+            assert_eq!(with.periods, without.periods);
+        }
+    }
+
+    #[test]
+    fn tiles() {
+        let def = SetDefinition::new(0.0, 3.125, 0.0, 1.09375, 200, 2, 100, 2.0);
+        assert_eq!(def.height_px, 70);
+        let tiles = def.tiles(64);
+        let positions = tiles.iter().map(|tile| (tile.x_px, tile.y_px, tile.def.width_px, tile.def.height_px)).collect::<Vec<_>>();
+        assert_eq!(positions, vec![(0, 0, 64, 64), (64, 0, 64, 64), (128, 0, 64, 64), (192, 0, 8, 64),
+            (0, 64, 64, 6), (64, 64, 64, 6), (128, 64, 64, 6), (192, 64, 8, 6)]);
+        assert_eq!(tiles[6].def.origin, Complex::new(2.0, 1.0));
+        assert_eq!(tiles.iter().map(|tile| tile.def.sample_count()).sum::<usize>(), def.sample_count());
+    }
+
+    #[test]
+    fn parallel_gives_same_results() {
+        // the sample positions are exact binary fractions so they are the same in the tiles
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 0.25, 192, 2, 200, 2.0)
+            .with_interior(InteriorColouring::Period)
+            .with_field_lines(true)
+            .with_symmetry(false);
+        let serial = super::calc_set(&def, &Mandelbrot, None);
+        let parallel = super::calc_set_parallel(&def, &Mandelbrot, 3);
+        assert_eq!(parallel.data, serial.data);
+        assert_eq!(super::calc_set_parallel(&def.with_cost_estimate(true), &Mandelbrot, 3).data, serial.data);
+        let adaptive = def.with_adaptive(Some(AdaptiveSampling { iterations: 3, shade: 0.1 }));
+        assert_eq!(super::calc_set_parallel(&adaptive, &Mandelbrot, 3).data, super::calc_set(&adaptive, &Mandelbrot, None).data);
+        let sobol = def.with_pattern(SamplePattern::Sobol, 7);
+        assert_eq!(super::calc_set_parallel(&sobol, &Mandelbrot, 3).data, super::calc_set(&sobol, &Mandelbrot, None).data);
+        assert_eq!(parallel.interior, serial.interior);
+        assert_eq!(parallel.shading, serial.shading);
+        assert_eq!(parallel.periods, serial.periods);
+    }
+
+    #[test]
+    fn with_view() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 120, 2, 100, 2.0);
+        assert_eq!(def.with_view(None, None), def);
+        let centre = FixedComplex::parse("-0.75,0.125").unwrap();
+        let zoomed = def.with_view(Some(centre), Some(FloatExp::from_f64(0.0078125)));
+        assert_eq!((zoomed.origin, zoomed.px_size), (Complex::new(-1.21875, -0.1875), FloatExp::from_f64(0.0078125)));
+        let moved = def.with_view(Some(centre), None);
+        assert_eq!(moved.origin, Complex::new(-2.25, -0.875));
+        assert_eq!(def.with_view(None, Some(FloatExp::from_f64(0.0125))).origin, Complex::new(-1.25, -0.5));
+    }
+
+    #[test]
+    fn deep_zoom() {
+        // samples 10⁻²⁰ apart are the same point in f64, so only double-double shows any detail
+        let centre = FixedComplex::parse("-0.743643887037158704752191506114774,0.131825904205311970493132056385139").unwrap();
+        let def = SetDefinition::new(0.0, 40.0, 0.0, 8.0, 40, 1, 10000, 2.0)
+            .with_view(Some(centre), Some(FloatExp::from_f64(1e-20)))
+            .with_perturbation(false);
+        assert_eq!(def.precision(), Precision::DoubleDouble);
+        assert!(def.exact_origin != FixedComplex::from_complex(def.origin));
+        let distinct = |data: &[u32]| {
+            let mut values = data.to_vec();
+            values.sort();
+            values.dedup();
+            values.len()
         };
-        let expected = vec![
-            SetDefinition { origin: Complex::new(1.0, 2.0), height_px: 34, ..def },
-            SetDefinition { origin: Complex::new(1.0, 2.34), height_px: 33, ..def },
-            SetDefinition { origin: Complex::new(1.0, 2.67), height_px: 33, ..def },
-        ];
-        assert_eq!(def.split(3), expected);
+        let deep = super::calc_set(&def, &Mandelbrot, None);
+        assert!(distinct(&deep.data) > 20);
+        let double = super::calc_set(&def.with_precision(Some(Precision::Double)), &Mandelbrot, None);
+        assert!(distinct(&double.data) < 3);
+        // the origins of the tiles keep their low parts
+        assert_eq!(super::calc_set_parallel(&def, &Mandelbrot, 3).data, deep.data);
+    }
+
+    #[test]
+    fn fixed_zoom() {
+        // at the tip of the antenna, samples 10⁻¹⁰⁰ apart escape after different numbers of
+        // iterations depending on their distance from -2
+        let centre = FixedComplex::parse("-2.0,0.0").unwrap();
+        let def = SetDefinition::new(0.0, 40.0, 0.0, 8.0, 40, 1, 1000, 2.0)
+            .with_view(Some(centre), Some(FloatExp::from_f64(1e-100)))
+            .with_perturbation(false);
+        assert_eq!(def.precision(), Precision::Fixed);
+        let fixed = super::calc_set(&def, &Mandelbrot, None);
+        let mut values = fixed.data[160..200].to_vec();
+        values.sort();
+        values.dedup();
+        assert!(values.len() > 2);
+        // the coordinates are the sums of a few f64 values, which double-double holds exactly
+        let double_double = super::calc_set(&def.with_precision(Some(Precision::DoubleDouble)), &Mandelbrot, None);
+        assert_eq!(double_double.data, fixed.data);
+        assert_eq!(super::calc_set_parallel(&def, &Mandelbrot, 3).data, fixed.data);
+    }
+
+    #[test]
+    fn ultra_deep_zoom() {
+        // the samples 10⁻⁴⁰⁰ from -2 escape after about 660 iterations, but in f64 their
+        // deltas from the reference at -2 underflow and they never escape
+        let centre = FixedComplex::parse("-2.0,0.0").unwrap();
+        let def = SetDefinition::new(0.0, 8.0, 0.0, 2.0, 8, 1, 1000, 10.0)
+            .with_view(Some(centre), Some(FloatExp::parse("1e-400").unwrap()));
+        assert_eq!(def.precision(), Precision::Fixed);
+        let perturbed = super::calc_set(&def, &Mandelbrot, ReferenceOrbit::primary(&def).as_ref());
+        assert!(perturbed.data.iter().any(|&iters| iters > 600));
+        let direct = super::calc_set(&def.with_perturbation(false), &Mandelbrot, None);
+        assert_eq!(perturbed.data, direct.data);
+        assert_eq!(super::calc_set_parallel(&def, &Mandelbrot, 3).data, perturbed.data);
+    }
+
+    #[test]
+    fn perturbation_matches_direct() {
+        let centre = FixedComplex::parse("-0.743643887037158704752191506114774,0.131825904205311970493132056385139").unwrap();
+        let def = SetDefinition::new(0.0, 40.0, 0.0, 8.0, 40, 1, 10000, 2.0)
+            .with_view(Some(centre), Some(FloatExp::from_f64(1e-20)));
+        assert!(def.uses_perturbation());
+        assert!(!def.with_perturbation(false).uses_perturbation());
+        let reference = ReferenceOrbit::primary(&def);
+        assert!(reference.is_some());
+        // the orbits are rounded differently, which changes a few samples deep in the spirals
+        let perturbed = super::calc_set(&def, &Mandelbrot, reference.as_ref());
+        let direct = super::calc_set(&def, &Mandelbrot, None);
+        let same = perturbed.data.iter().zip(&direct.data).filter(|&(a, b)| a == b).count();
+        assert!(same > perturbed.data.len() * 99 / 100);
+        assert_eq!(super::calc_set_parallel(&def, &Mandelbrot, 3).data, perturbed.data);
+    }
+
+    #[test]
+    fn series_approximation_matches_iteration() {
+        // the series is rounded differently from the iterated deltas, which changes a few samples
+        let centre = FixedComplex::parse("-0.743643887037158704752191506114774,0.131825904205311970493132056385139").unwrap();
+        let def = SetDefinition::new(0.0, 40.0, 0.0, 8.0, 40, 1, 10000, 2.0)
+            .with_view(Some(centre), Some(FloatExp::from_f64(1e-20)));
+        let series = super::calc_set(&def, &Mandelbrot, ReferenceOrbit::primary(&def).as_ref());
+        let def = def.with_series_approximation(false);
+        let iterated = super::calc_set(&def, &Mandelbrot, ReferenceOrbit::primary(&def).as_ref());
+        let same = series.data.iter().zip(&iterated.data).filter(|&(a, b)| a == b).count();
+        assert!(same > series.data.len() * 99 / 100);
+    }
+
+    #[test]
+    fn double_double_gives_similar_results() {
+        // the orbits are rounded differently, which changes a few samples near the boundary
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 120, 2, 200, 2.0);
+        let double = super::calc_set(&def.with_precision(Some(Precision::Double)), &Mandelbrot, None);
+        let double_double = super::calc_set(&def.with_precision(Some(Precision::DoubleDouble)), &Mandelbrot, None);
+        let same = double.data.iter().zip(&double_double.data).filter(|&(a, b)| a == b).count();
+        assert!(same > double.data.len() * 99 / 100);
+    }
+
+    #[test]
+    fn single_gives_similar_results() {
+        // the samples of a small view are far enough apart for f32, which only changes a few
+        // samples near the boundary where the orbits are chaotic
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 120, 2, 200, 2.0);
+        assert_eq!(def.precision(), Precision::Single);
+        assert!(!def.uses_perturbation());
+        let single = super::calc_set(&def, &Mandelbrot, None);
+        let double = super::calc_set(&def.with_precision(Some(Precision::Double)), &Mandelbrot, None);
+        let different = single.data.iter().zip(&double.data).filter(|&(a, b)| a != b).count();
+        assert!(different * 200 < single.data.len(), "{} of {}", different, single.data.len());
+    }
+
+    #[test]
+    fn parallel_uses_precision_of_set() {
+        // the tiles near the origin are far enough from it for f32 to position their samples,
+        // but they're calculated in f64 like the rest of the set
+        let def = SetDefinition::new(-1.5, 0.5, -0.5, 0.5, 350, 2, 200, 2.0).with_symmetry(false);
+        assert_eq!(def.precision(), Precision::Double);
+        assert_eq!(def.tile(245, 70, 16, 16).def.precision(), Precision::Single);
+        let serial = super::calc_set(&def, &Mandelbrot, None);
+        assert_eq!(super::calc_set_parallel(&def, &Mandelbrot, 3).data, serial.data);
+        assert_eq!(super::calc_set(&def.with_precision(Some(Precision::Double)), &Mandelbrot, None).data, serial.data);
+    }
+
+    #[test]
+    fn f64_colourings() {
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 1.0, 30, 1, 100, 2.0);
+        assert_eq!(def.f64_colouring(), None);
+        assert_eq!(def.with_interior(InteriorColouring::Period).f64_colouring(), Some("--interior"));
+        assert_eq!(def.with_exterior(ExteriorColouring::TrapDistance, OrbitTrap::Point { centre: Complex::new(0.0, 0.0) }).f64_colouring(), Some("--exterior"));
+        assert_eq!(def.with_lighting(Some(Lighting::new(45.0, 45.0))).f64_colouring(), Some("--light"));
+        assert_eq!(def.with_field_lines(true).f64_colouring(), Some("--field-lines"));
     }
 }
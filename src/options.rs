@@ -0,0 +1,289 @@
+use interior::InteriorColouring;
+use shading::Lighting;
+use exterior::ExteriorColouring;
+use trap::OrbitTrap;
+use subdivision::Subdivision;
+use adaptive::AdaptiveSampling;
+use pattern::SamplePattern;
+use precision::Precision;
+use fixed::{self, FixedComplex};
+use floatexp::FloatExp;
+use std::str::FromStr;
+use complex::Complex;
+use fractal::{Fractal, Julia};
+
+/// Options controlling how the set is calculated and rendered, parsed from the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub threads: u32,
+    pub interior: InteriorColouring,
+    pub lighting: Option<Lighting>,
+    pub exterior: ExteriorColouring,
+    pub trap: OrbitTrap,
+    /// Path of the image used by the image orbit trap.
+    pub texture: Option<String>,
+    pub field_lines: bool,
+    pub interior_check: bool,
+    pub periodicity: bool,
+    pub subdivision: Subdivision,
+    pub symmetry: bool,
+    pub cost_estimate: bool,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub pattern: SamplePattern,
+    pub seed: u64,
+    /// Whether every sample of the set is stored before the image is rendered, rather than
+    /// the samples being combined into pixels as they are calculated.
+    pub sample_buffer: bool,
+    /// The memory in megabytes the set is calculated in when it's written as a PNG or TIFF
+    /// image without the sample buffer. Larger images are calculated and written in bands.
+    pub memory_limit: u64,
+    /// The centre of the view, which replaces the default view's centre if it's given.
+    pub centre: Option<FixedComplex>,
+    /// The width of each pixel, which replaces the default view's pixel size if it's given.
+    pub px_size: Option<FloatExp>,
+    /// The precision the set is calculated in, or `None` if it's chosen from the zoom.
+    pub precision: Option<Precision>,
+    /// Whether deep zooms are calculated relative to reference orbits.
+    pub perturbation: bool,
+    /// Whether the first iterations are skipped using a series approximation in deep zooms.
+    pub series_approximation: bool,
+    /// The constant of the Julia set rendered instead of the Mandelbrot set, if it's given. The
+    /// Julia set can only be calculated in single or double precision.
+    pub julia: Option<Complex>,
+    pub output: String,
+}
+
+impl Options {
+    /// Parses the options from command line arguments, not including the program name.
+    ///
+    /// Each option is a flag followed by its value, for example `--threads 4`. Options that
+    /// aren't specified take their default values.
+    pub fn parse<I>(args: I) -> Result<Options, String> where I: IntoIterator<Item = String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(format!("No value given for option '{}'", flag)),
+            };
+            match flag.as_str() {
+                "--threads" => options.threads = parse_name(&flag, &value, parse_threads)?,
+                "--interior" => options.interior = parse_name(&flag, &value, InteriorColouring::from_name)?,
+                "--light" => options.lighting = Some(parse_name(&flag, &value, Lighting::parse)?),
+                "--exterior" => options.exterior = parse_name(&flag, &value, ExteriorColouring::from_name)?,
+                "--trap" => options.trap = parse_name(&flag, &value, OrbitTrap::parse)?,
+                "--texture" => options.texture = Some(value),
+                "--field-lines" => options.field_lines = parse_bool(&flag, &value)?,
+                "--interior-check" => options.interior_check = parse_bool(&flag, &value)?,
+                "--periodicity" => options.periodicity = parse_bool(&flag, &value)?,
+                "--subdivision" => options.subdivision = parse_name(&flag, &value, Subdivision::from_name)?,
+                "--symmetry" => options.symmetry = parse_bool(&flag, &value)?,
+                "--cost-estimate" => options.cost_estimate = parse_bool(&flag, &value)?,
+                "--adaptive" => options.adaptive = Some(parse_name(&flag, &value, AdaptiveSampling::parse)?),
+                "--pattern" => options.pattern = parse_name(&flag, &value, SamplePattern::from_name)?,
+                "--seed" => options.seed = parse_number(&flag, &value)?,
+                "--sample-buffer" => options.sample_buffer = parse_bool(&flag, &value)?,
+                "--memory-limit" => options.memory_limit = parse_number(&flag, &value)?,
+                "--centre" => options.centre = Some(parse_name(&flag, &value, FixedComplex::parse)?),
+                "--pixel-size" => options.px_size = Some(parse_name(&flag, &value, parse_px_size)?),
+                "--precision" => options.precision = parse_name(&flag, &value, parse_precision)?,
+                "--perturbation" => options.perturbation = parse_bool(&flag, &value)?,
+                "--series-approximation" => options.series_approximation = parse_bool(&flag, &value)?,
+                "--julia" => options.julia = Some(parse_name(&flag, &value, parse_julia)?),
+                "--output" => options.output = value,
+                _ => return Err(format!("Unknown option '{}'", flag)),
+            }
+        }
+        if options.exterior == ExteriorColouring::TrapImage && options.texture.is_none() {
+            return Err("The image trap colouring needs a texture".to_string());
+        }
+        if options.exterior == ExteriorColouring::TrapImage && !matches!(options.trap, OrbitTrap::Rectangle { .. }) {
+            return Err("The image trap colouring needs a rectangle trap".to_string());
+        }
+        if let Some(c) = options.julia {
+            if matches!(options.precision, Some(Precision::DoubleDouble | Precision::Fixed)) {
+                return Err("The Julia set can only be calculated in single or double precision".to_string());
+            }
+            if !(Julia { c }).has_interior(options.interior) {
+                return Err("The interior of the Julia set can only be coloured black or by its final magnitude".to_string());
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            threads: 8,
+            interior: InteriorColouring::Black,
+            lighting: None,
+            exterior: ExteriorColouring::Iterations,
+            trap: OrbitTrap::Point { centre: Complex::new(0.0, 0.0) },
+            texture: None,
+            field_lines: false,
+            interior_check: true,
+            periodicity: true,
+            subdivision: Subdivision::Off,
+            symmetry: true,
+            cost_estimate: false,
+            adaptive: None,
+            pattern: SamplePattern::Grid,
+            seed: 0,
+            sample_buffer: false,
+            memory_limit: 1024,
+            centre: None,
+            px_size: None,
+            precision: None,
+            perturbation: true,
+            series_approximation: true,
+            julia: None,
+            output: "/Users/chris/tmp/mandelbrot.bmp".to_string(),
+        }
+    }
+}
+
+/// Parses the number of threads, which must be at least one.
+fn parse_threads(value: &str) -> Option<u32> {
+    value.parse().ok().filter(|&threads| threads > 0)
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value '{}' for option '{}'", value, flag))
+}
+
+/// Parses a pixel size, which must be positive and can be smaller than an f64 can hold, down
+/// to the smallest size the fixed-point numbers can position samples at.
+fn parse_px_size(value: &str) -> Option<FloatExp> {
+    FloatExp::parse(value).filter(|&px_size| px_size.mantissa > 0.0 && px_size.to_f64().is_finite() && fixed::holds_spacing(px_size))
+}
+
+/// Parses the constant of a Julia set, given as its real and imaginary parts separated by a
+/// comma.
+fn parse_julia(value: &str) -> Option<Complex> {
+    FixedComplex::parse(value).map(FixedComplex::to_complex)
+}
+
+/// Parses the name of a precision, where `auto` chooses the precision from the zoom.
+fn parse_precision(value: &str) -> Option<Option<Precision>> {
+    match value {
+        "auto" => Some(None),
+        _ => Precision::from_name(value).map(Some),
+    }
+}
+
+fn parse_bool(flag: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        _ => Err(format!("Invalid value '{}' for option '{}'", value, flag)),
+    }
+}
+
+fn parse_name<T, F>(flag: &str, value: &str, from_name: F) -> Result<T, String>
+    where F: Fn(&str) -> Option<T> {
+    from_name(value).ok_or_else(|| format!("Invalid value '{}' for option '{}'", value, flag))
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixed::Fixed;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_defaults() {
+        assert_eq!(Options::parse(args(&[])), Ok(Options::default()));
+    }
+
+    #[test]
+    fn parse_all() {
+        let options = Options::parse(args(&["--threads", "4", "--interior", "distance", "--light", "45,30",
+            "--exterior", "trap-image", "--trap", "rectangle:0,0,1,2", "--texture", "t.bmp", "--field-lines", "on",
+            "--interior-check", "off", "--periodicity", "off",
+            "--subdivision", "conservative", "--symmetry", "off",
+            "--cost-estimate", "on", "--adaptive", "2,0.05",
+            "--pattern", "sobol", "--seed", "12345678901", "--sample-buffer", "on", "--memory-limit", "256",
+            "--centre", "-0.75,1e-120", "--pixel-size", "1e-400", "--precision", "fixed", "--perturbation", "off",
+            "--series-approximation", "off", "--output", "a.bmp"]));
+        let expected = Options {
+            threads: 4,
+            interior: InteriorColouring::Distance,
+            lighting: Some(Lighting::new(45.0, 30.0)),
+            exterior: ExteriorColouring::TrapImage,
+            trap: OrbitTrap::Rectangle { corner: Complex::new(0.0, 0.0), size: Complex::new(1.0, 2.0) },
+            texture: Some("t.bmp".to_string()),
+            field_lines: true,
+            interior_check: false,
+            periodicity: false,
+            subdivision: Subdivision::Conservative,
+            symmetry: false,
+            cost_estimate: true,
+            adaptive: Some(AdaptiveSampling { iterations: 2, shade: 0.05 }),
+            pattern: SamplePattern::Sobol,
+            seed: 12_345_678_901,
+            sample_buffer: true,
+            memory_limit: 256,
+            centre: Some(FixedComplex::new(Fixed::from_f64(-0.75), Fixed::parse("1e-120").unwrap())),
+            px_size: Some(FloatExp::parse("1e-400").unwrap()),
+            precision: Some(Precision::Fixed),
+            perturbation: false,
+            series_approximation: false,
+            julia: None,
+            output: "a.bmp".to_string(),
+        };
+        assert_eq!(options, Ok(expected));
+    }
+
+    #[test]
+    fn parse_julia() {
+        let options = Options::parse(args(&["--julia", "-0.8,0.156", "--interior", "magnitude", "--precision", "single",
+            "--centre", "0.5,0", "--pixel-size", "1e-3"])).unwrap();
+        assert_eq!(options.julia, Some(Complex::new(-0.8, 0.156)));
+        assert_eq!(options.px_size, Some(FloatExp::from_f64(1e-3)));
+        assert_eq!(options.interior, InteriorColouring::FinalMagnitude);
+        assert_eq!(options.precision, Some(Precision::Single));
+    }
+
+    #[test]
+    fn parse_auto_precision() {
+        let options = Options::parse(args(&["--precision", "double", "--precision", "auto"])).unwrap();
+        assert_eq!(options.precision, None);
+        let options = Options::parse(args(&["--precision", "single"])).unwrap();
+        assert_eq!(options.precision, Some(Precision::Single));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Options::parse(args(&["--threads", "four"])).is_err());
+        assert!(Options::parse(args(&["--threads", "0"])).is_err());
+        assert!(Options::parse(args(&["--interior", "purple"])).is_err());
+        assert!(Options::parse(args(&["--light", "45"])).is_err());
+        assert!(Options::parse(args(&["--trap", "circle:0,0"])).is_err());
+        assert!(Options::parse(args(&["--exterior", "trap-image"])).is_err());
+        assert!(Options::parse(args(&["--exterior", "trap-image", "--texture", "t.bmp"])).is_err());
+        assert!(Options::parse(args(&["--field-lines", "yes please"])).is_err());
+        assert!(Options::parse(args(&["--subdivision", "lots"])).is_err());
+        assert!(Options::parse(args(&["--pattern", "random"])).is_err());
+        assert!(Options::parse(args(&["--seed", "-1"])).is_err());
+        assert!(Options::parse(args(&["--centre", "-0.75"])).is_err());
+        assert!(Options::parse(args(&["--pixel-size", "0"])).is_err());
+        assert!(Options::parse(args(&["--pixel-size", "1e400"])).is_err());
+        assert!(Options::parse(args(&["--pixel-size", "1e-600"])).is_err());
+        assert!(Options::parse(args(&["--pixel-size", "1e-1000"])).is_err());
+        assert!(Options::parse(args(&["--precision", "quad"])).is_err());
+        assert!(Options::parse(args(&["--julia", "0.3"])).is_err());
+        assert!(Options::parse(args(&["--julia", "-0.8,0.156", "--precision", "double-double"])).is_err());
+        assert!(Options::parse(args(&["--julia", "-0.8,0.156", "--precision", "fixed"])).is_err());
+        assert!(Options::parse(args(&["--julia", "-0.8,0.156", "--interior", "period"])).is_err());
+        assert!(Options::parse(args(&["--julia", "-0.8,0.156", "--interior", "distance"])).is_err());
+        assert!(Options::parse(args(&["--threads"])).is_err());
+        assert!(Options::parse(args(&["--colour", "red"])).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use double_double::{DdComplex, DoubleDouble};
+
+/// The positions of the samples within each pixel.
+///
+/// Every pattern has the same number of samples per pixel, the square of the oversampling,
+/// and each sample has its own slot in the set data. The samples are averaged into the colour
+/// of the pixel in the same way whatever their positions. The patterns other than the regular
+/// grid avoid the moiré patterns produced when fine periodic structures are sampled on a
+/// regular grid. The random positions depend on a seed and on the pixel, so sets are always
+/// calculated the same way for the same seed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplePattern {
+    /// The samples are on a regular grid with the first sample at the bottom left corner of
+    /// the pixel.
+    Grid,
+    /// The pixel is divided into a regular grid of cells with a sample at a random position
+    /// in each cell.
+    Jittered,
+    /// The samples are on a regular grid rotated so no two samples are in the same row or
+    /// column, which samples horizontal and vertical edges at more positions than a grid.
+    RotatedGrid,
+    /// The samples are the first points of a Sobol sequence, randomly shifted in each pixel.
+    /// The points are spread evenly across the pixel without the regularity of a grid.
+    Sobol,
+}
+
+impl SamplePattern {
+    /// Returns the pattern whose name is `name`, or `None` if there isn't one.
+    pub fn from_name(name: &str) -> Option<SamplePattern> {
+        match name {
+            "grid" => Some(SamplePattern::Grid),
+            "jittered" => Some(SamplePattern::Jittered),
+            "rotated" => Some(SamplePattern::RotatedGrid),
+            "sobol" => Some(SamplePattern::Sobol),
+            _ => None,
+        }
+    }
+
+    /// Returns the position of sample `slot` of a pixel with `oversampling` squared samples,
+    /// as fractions of the pixel size from its bottom left corner, each in the range 0 to 1.
+    ///
+    /// `pixel` identifies the pixel for the random patterns and can be found with
+    /// `pixel_index`.
+    pub fn position(&self, pixel: (i64, i64), slot: usize, oversampling: usize, seed: u64) -> (f64, f64) {
+        let n = oversampling as f64;
+        let (col, row) = ((slot % oversampling) as f64, (slot / oversampling) as f64);
+
+        match *self {
+            SamplePattern::Grid => (col / n, row / n),
+            SamplePattern::Jittered => {
+                let hash = random(seed, pixel, slot as u64);
+                ((col + fraction(hash >> 32)) / n, (row + fraction(hash)) / n)
+            }
+            SamplePattern::RotatedGrid => {
+                // the grid is rotated by atan(1 / n) and scaled so each sample has its own column
+                // and row out of n² columns and rows
+                ((col + (row + 0.5) / n) / n, (row + (n - 1.0 - col + 0.5) / n) / n)
+            }
+            SamplePattern::Sobol => {
+                // a random digital shift of the whole sequence keeps the points evenly spread
+                let (x, y) = sobol(slot as u32);
+                let shift = random(seed, pixel, u64::MAX);
+                (fraction(u64::from(x) ^ (shift >> 32)), fraction(u64::from(y) ^ shift))
+            }
+        }
+    }
+}
+
+/// Returns the index of the pixel with its bottom left corner at `corner` in the grid of
+/// pixels of size `px_size` whose origin is zero. This is found once for the origin of a set,
+/// and the pixels of its tiles are counted from it, so the index doesn't depend on how the set
+/// is split up for calculating in parallel.
+///
+/// A pixel size too small for an f64 gives the index zero, which still gives each pixel of
+/// the set a different index.
+pub fn pixel_index(corner: DdComplex, px_size: f64) -> (i64, i64) {
+    if px_size == 0.0 {
+        return (0, 0);
+    }
+    let index = |coord: DoubleDouble| {
+        let ratio = coord / DoubleDouble::from_f64(px_size);
+        // in deep zooms the index is too big for an i64, but its low bits still tell the
+        // pixels apart
+        (ratio.hi.round() as i128).wrapping_add(ratio.lo.round() as i128) as i64
+    };
+    (index(corner.real), index(corner.imag))
+}
+
+/// Returns the point `index` of the two dimensional Sobol sequence, as fractions of 2³².
+fn sobol(index: u32) -> (u32, u32) {
+    // the first dimension is the van der Corput sequence, the direction numbers of the second
+    // come from the primitive polynomial x + 1
+    let (mut x, mut y) = (0, 0);
+    let (mut dir_x, mut dir_y) = (1u32 << 31, 1u32 << 31);
+    let mut bits = index;
+
+    while bits != 0 {
+        if bits & 1 != 0 {
+            x ^= dir_x;
+            y ^= dir_y;
+        }
+        bits >>= 1;
+        dir_x >>= 1;
+        dir_y ^= dir_y >> 1;
+    }
+    (x, y)
+}
+
+/// Converts the low 32 bits of `bits` into a fraction in the range 0 to 1.
+fn fraction(bits: u64) -> f64 {
+    (bits & 0xffff_ffff) as f64 / (1u64 << 32) as f64
+}
+
+/// Returns pseudorandom bits for a sample of a pixel.
+fn random(seed: u64, pixel: (i64, i64), slot: u64) -> u64 {
+    mix(mix(mix(mix(seed) ^ pixel.0 as u64) ^ pixel.1 as u64) ^ slot)
+}
+
+/// The finalising mix of the SplitMix64 random number generator, which spreads any change in
+/// the input over all the bits of the output.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS: [SamplePattern; 4] =
+        [SamplePattern::Grid, SamplePattern::Jittered, SamplePattern::RotatedGrid, SamplePattern::Sobol];
+
+    fn positions(pattern: SamplePattern, pixel: (i64, i64), oversampling: usize, seed: u64) -> Vec<(f64, f64)> {
+        (0..oversampling * oversampling).map(|slot| pattern.position(pixel, slot, oversampling, seed)).collect()
+    }
+
+    /// Returns the number of samples in each of `count` equal strips across the pixel, using
+    /// `coord` to pick the coordinate across the strips.
+    fn strata<F>(positions: &[(f64, f64)], count: usize, coord: F) -> Vec<usize> where F: Fn(&(f64, f64)) -> f64 {
+        let mut strata = vec![0; count];
+
+        for position in positions {
+            strata[(coord(position) * count as f64) as usize] += 1;
+        }
+        strata
+    }
+
+    #[test]
+    fn in_pixel() {
+        for &pattern in &PATTERNS {
+            for oversampling in 1..6 {
+                let positions = positions(pattern, (5, -9), oversampling, 1);
+                assert!(positions.iter().all(|&(x, y)| (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y)));
+            }
+        }
+    }
+
+    #[test]
+    fn one_sample_per_cell() {
+        for &pattern in &PATTERNS {
+            let positions = positions(pattern, (5, -9), 4, 1);
+            let cells = positions.iter().map(|&(x, y)| ((x * 4.0) as usize, (y * 4.0) as usize)).collect::<Vec<_>>();
+            assert!((0..4).all(|col| (0..4).all(|row| cells.contains(&(col, row)))), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn one_sample_per_row_and_column() {
+        // with 16 samples every sample is in a different one of 16 rows and columns
+        for &pattern in &[SamplePattern::RotatedGrid, SamplePattern::Sobol] {
+            let positions = positions(pattern, (5, -9), 4, 1);
+            assert!(strata(&positions, 16, |&(x, _)| x).iter().all(|&count| count == 1), "{:?}", pattern);
+            assert!(strata(&positions, 16, |&(_, y)| y).iter().all(|&count| count == 1), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn reproducible_from_seed() {
+        for &pattern in &[SamplePattern::Jittered, SamplePattern::Sobol] {
+            assert_eq!(positions(pattern, (5, -9), 3, 1), positions(pattern, (5, -9), 3, 1));
+            assert_ne!(positions(pattern, (5, -9), 3, 1), positions(pattern, (5, -9), 3, 2));
+            assert_ne!(positions(pattern, (5, -9), 3, 1), positions(pattern, (6, -9), 3, 1));
+        }
+    }
+
+    #[test]
+    fn sobol() {
+        let points = (0..4).map(super::sobol).collect::<Vec<_>>();
+        assert_eq!(points, vec![(0, 0), (1 << 31, 1 << 31), (1 << 30, 3 << 30), (3 << 30, 1 << 30)]);
+    }
+}
@@ -0,0 +1,283 @@
+use complex::Complex;
+use fixed::{self, FixedComplex};
+use floatexp::FeComplex;
+use precision::{Precision, Real};
+use series::SeriesApproximation;
+use {batch_escape_offsets, SetDefinition};
+
+/// The fraction of the magnitude of the reference orbit below which the magnitude of a
+/// perturbed orbit shows that the point is glitched, from Pauldelbrot's criterion. The delta
+/// from the reference is then so much bigger than the orbit point that f64 can't represent the
+/// difference accurately.
+pub const GLITCH_TOLERANCE: f64 = 1e-3;
+/// The most references used for the points of each tile, including the primary reference. The
+/// points still glitched with the last reference are calculated directly.
+const MAX_REFERENCES: usize = 8;
+/// The smallest distance between samples whose deltas from the reference orbit are iterated in
+/// f64. The deltas of closer samples are iterated as `FloatExp`s, which is several times
+/// slower, because they would underflow when the orbit gets close to the reference orbit.
+const DOUBLE_DELTA_MIN_SPACING: f64 = 1e-280;
+
+/// The orbit of a reference point calculated in the full precision of a set, rounded to f64,
+/// which the orbits of nearby points are calculated relative to.
+pub struct ReferenceOrbit {
+    point: FixedComplex,
+    /// The orbit points up to and including the first one outside the escape radius, starting
+    /// from the reference point itself.
+    orbit: Vec<Complex>,
+    /// The series which skips the first iterations of the points, if there is one.
+    series: Option<SeriesApproximation>,
+}
+
+impl ReferenceOrbit {
+    /// Calculates the orbit of `point` in the precision of `set_def`.
+    pub fn new(set_def: &SetDefinition, point: FixedComplex) -> ReferenceOrbit {
+        let (max_iterations, escape_radius) = (set_def.max_iterations, set_def.escape_radius);
+        let orbit = match set_def.precision() {
+            Precision::Fixed => {
+                let point = point.with_limbs(fixed::limbs_for_spacing(set_def.sample_size()));
+                reference_orbit(Complex::new(point.real, point.imag), max_iterations, escape_radius)
+            }
+            Precision::Single | Precision::Double | Precision::DoubleDouble => {
+                reference_orbit(point.to_dd(), max_iterations, escape_radius)
+            }
+        };
+        ReferenceOrbit { point, orbit, series: None }
+    }
+
+    /// Returns the primary reference orbit for a set, which is at the centre of the set, or
+    /// `None` if the set isn't calculated using perturbation. It has the series approximation
+    /// for the set unless the set doesn't use one.
+    pub fn primary(set_def: &SetDefinition) -> Option<ReferenceOrbit> {
+        if !set_def.uses_perturbation() {
+            return None;
+        }
+        let (width, height) = (set_def.width_px as usize, set_def.height_px as usize);
+        let centre = set_def.px_offset((width as f64) / 2.0, (height as f64) / 2.0);
+        let mut reference = ReferenceOrbit::new(set_def, set_def.exact_origin + centre);
+
+        if set_def.series_approximation {
+            let origin_delta = (set_def.exact_origin - reference.point).to_fe_complex();
+            let corners = [(0, 0), (width, 0), (0, height), (width, height)].iter()
+                .map(|&(x, y)| origin_delta + set_def.pixel_offset(x, y))
+                .collect::<Vec<_>>();
+            let escape_value = set_def.escape_radius * set_def.escape_radius;
+            reference.series = SeriesApproximation::new(&reference.orbit, &corners, escape_value);
+            info!("series approximation skips {} iterations", reference.series.as_ref().map_or(0, |series| series.skipped()));
+        }
+        Some(reference)
+    }
+}
+
+/// The reference orbits the samples of one tile are calculated relative to, which are the
+/// primary reference shared by the whole set and the secondary references added for the
+/// tile's glitched points. The secondary references are kept for the later batches of the
+/// tile, whose glitches are usually in the same places.
+pub struct References<'a> {
+    primary: &'a ReferenceOrbit,
+    secondary: Vec<ReferenceOrbit>,
+}
+
+impl<'a> References<'a> {
+    /// Returns the references of a tile before any secondary references have been added.
+    pub fn new(primary: &'a ReferenceOrbit) -> References<'a> {
+        References { primary, secondary: Vec::new() }
+    }
+}
+
+/// Returns the orbit of `point` rounded to f64, up to the first point outside the escape radius
+/// or the maximum iterations.
+fn reference_orbit<T: Real>(point: Complex<T>, max_iterations: u32, escape_radius: f64) -> Vec<Complex> {
+    let escape_value = escape_radius * escape_radius;
+    let mut orbit = Vec::new();
+    let mut z = point;
+
+    for _ in 0..max_iterations {
+        let rounded = z.to_complex();
+        orbit.push(rounded);
+
+        if rounded.norm_sqr() > escape_value {
+            break;
+        }
+        let zri = z.real * z.imag;
+        z = Complex::new(z.real * z.real - z.imag * z.imag + point.real, zri + zri + point.imag);
+    }
+    orbit
+}
+
+/// Returns the escape iterations of the point whose real and imaginary distances from the
+/// reference point are `delta_c`, found by iterating its delta from the reference orbit in the
+/// precision of `T`, starting from the delta `delta` after `skipped` iterations. This is zero
+/// if it doesn't escape within the maximum iterations.
+///
+/// Returns an error holding the squared magnitude of the orbit point where the point was found
+/// to be glitched, or infinity if the reference orbit escaped first.
+fn perturbed_iterations<T: Real>(orbit: &[Complex], delta_c: Complex<T>, skipped: usize, mut delta: Complex<T>, max_iterations: u32, escape_value: f64) -> Result<u32, f64> {
+    let glitch_value = GLITCH_TOLERANCE * GLITCH_TOLERANCE;
+
+    for (i, &reference) in orbit.iter().enumerate().skip(skipped) {
+        let z = reference + delta.to_complex();
+
+        if z.norm_sqr() > escape_value {
+            return Ok(i as u32);
+        }
+        if z.norm_sqr() < glitch_value * reference.norm_sqr() {
+            return Err(z.norm_sqr());
+        }
+        // z² + c = (Z + δ)² + C + δc, and the reference orbit is Z² + C
+        let two_z = Complex::from_complex(reference * 2.0);
+        delta = two_z * delta + delta * delta + delta_c;
+    }
+    if orbit.len() < max_iterations as usize {
+        Err(f64::INFINITY)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Calculates the escape iterations of a batch of points given by their offsets from the
+/// origin of `set_def`, iterating each point's delta from the primary reference of
+/// `references` in f64, or as `FloatExp`s if the points are too close together for f64.
+///
+/// The first iterations are skipped using the series approximation of `reference` if it has
+/// one. The glitched points are recalculated relative to each secondary reference in turn,
+/// without skipping any iterations, and the points glitched with all of them are recalculated
+/// relative to a new one at the glitched point whose orbit came closest to zero, which is where
+/// the glitch is usually centred. The orbits aren't checked for periodicity, so the periods are
+/// all zero.
+pub fn batch_escape_perturbed(set_def: &SetDefinition, references: &mut References, offsets: &[FeComplex], iterations: &mut [u32], periods: &mut [u32]) {
+    let secondary = &mut references.secondary;
+    let escape_value = set_def.escape_radius * set_def.escape_radius;
+    let floatexp = set_def.sample_size().to_f64() < DOUBLE_DELTA_MIN_SPACING;
+    let mut glitched = (0..offsets.len()).collect::<Vec<_>>();
+    let mut closest = (f64::INFINITY, 0);
+
+    for period in periods.iter_mut() {
+        *period = 0;
+    }
+    for i in 0..MAX_REFERENCES {
+        if i > secondary.len() {
+            secondary.push(ReferenceOrbit::new(set_def, set_def.exact_origin + offsets[closest.1]));
+        }
+        let reference = if i == 0 { references.primary } else { &secondary[i - 1] };
+        // the origin is close to the reference, so the difference between them is accurate with
+        // an f64 mantissa
+        let origin_delta = (set_def.exact_origin - reference.point).to_fe_complex();
+        closest = (f64::INFINITY, 0);
+        let iterate = |delta_c: FeComplex| {
+            let (skipped, delta) = match reference.series {
+                Some(ref series) => (series.skipped(), series.delta(delta_c)),
+                None => (0, delta_c),
+            };
+            if floatexp {
+                perturbed_iterations(&reference.orbit, delta_c, skipped, delta, set_def.max_iterations, escape_value)
+            } else {
+                perturbed_iterations(&reference.orbit, delta_c.to_complex(), skipped, delta.to_complex(), set_def.max_iterations, escape_value)
+            }
+        };
+
+        glitched.retain(|&idx| match iterate(origin_delta + offsets[idx]) {
+            Ok(iters) => {
+                iterations[idx] = iters;
+                false
+            }
+            Err(z_norm) => {
+                if z_norm <= closest.0 {
+                    closest = (z_norm, idx);
+                }
+                true
+            }
+        });
+        if glitched.is_empty() {
+            return;
+        }
+    }
+    debug!("calculating {} glitched points directly", glitched.len());
+    let glitched_offsets = glitched.iter().map(|&idx| offsets[idx]).collect::<Vec<_>>();
+    let mut glitched_iterations = vec![0; glitched.len()];
+    let mut glitched_periods = vec![0; glitched.len()];
+    batch_escape_offsets(set_def, None, &glitched_offsets, &mut glitched_iterations, &mut glitched_periods);
+
+    for (&idx, (&iters, &period)) in glitched.iter().zip(glitched_iterations.iter().zip(&glitched_periods)) {
+        iterations[idx] = iters;
+        periods[idx] = period;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixed::Fixed;
+    use floatexp::FloatExp;
+    use precision;
+
+    #[test]
+    fn perturbed_matches_direct() {
+        // points near the boundary in the seahorse valley, where the escape iterations change
+        // quickly
+        let centre = FixedComplex::parse("-0.743643887,0.131825904").unwrap();
+        let orbit = reference_orbit(centre.to_dd(), 1000, 2.0);
+        assert_eq!(orbit.len(), 1000);
+        let mut glitches = 0;
+
+        for i in 0..100 {
+            let delta_c = Complex::new((i % 10) as f64 * 1e-8, (i / 10) as f64 * 1e-8);
+            let point = (centre + FeComplex::from_complex(delta_c)).to_dd();
+            let (direct, _) = precision::escape_iterations(point, 1000, 2.0, None);
+            let perturbed = perturbed_iterations(&orbit, delta_c, 0, delta_c, 1000, 4.0);
+
+            match perturbed {
+                Ok(iters) => assert_eq!(iters, direct, "{}", i),
+                Err(_) => glitches += 1,
+            }
+            // the arithmetic of FloatExp is the same as f64 in its range
+            let fe_delta_c = FeComplex::from_complex(delta_c);
+            assert_eq!(perturbed_iterations(&orbit, fe_delta_c, 0, fe_delta_c, 1000, 4.0), perturbed);
+        }
+        assert!(glitches < 10, "{}", glitches);
+    }
+
+    #[test]
+    fn reference_escapes_first() {
+        // the reference escapes after a few iterations, but a point in the set doesn't
+        let orbit = reference_orbit(Complex::new(Fixed::from_f64(0.5), Fixed::from_f64(0.0)), 100, 2.0);
+        assert_eq!(orbit.len(), 5);
+        let (inside, zero) = (Complex::new(-0.6, 0.0), Complex::new(0.0, 0.0));
+        assert_eq!(perturbed_iterations(&orbit, inside, 0, inside, 100, 4.0), Err(f64::INFINITY));
+        assert_eq!(perturbed_iterations(&orbit, zero, 0, zero, 100, 4.0), Ok(4));
+    }
+
+    #[test]
+    fn primary_series() {
+        // the series skips many iterations of a deep zoom, unless it's turned off
+        let centre = FixedComplex::parse("-0.743643887037158704752191506114774,0.131825904205311970493132056385139").unwrap();
+        let def = SetDefinition::new(0.0, 40.0, 0.0, 8.0, 40, 1, 10000, 2.0)
+            .with_view(Some(centre), Some(FloatExp::from_f64(1e-20)));
+        let reference = ReferenceOrbit::primary(&def).unwrap();
+        assert!(reference.series.unwrap().skipped() > 1000);
+        let reference = ReferenceOrbit::primary(&def.with_series_approximation(false)).unwrap();
+        assert_eq!(reference.series, None);
+    }
+
+    #[test]
+    fn secondary_references_reused() {
+        // the glitched points of a second batch are fixed by the secondary references made for
+        // the first, so no more are added and the points are calculated the same way
+        let centre = FixedComplex::parse("-0.743643887037158704752191506114774,0.131825904205311970493132056385139").unwrap();
+        let def = SetDefinition::new(0.0, 40.0, 0.0, 8.0, 40, 1, 10000, 2.0)
+            .with_view(Some(centre), Some(FloatExp::from_f64(1e-20)));
+        let primary = ReferenceOrbit::primary(&def).unwrap();
+        let offsets = (0..320).map(|idx| def.pixel_offset(idx % 40, idx / 40)).collect::<Vec<_>>();
+        let mut references = References::new(&primary);
+        let (mut iterations, mut periods) = (vec![0; 320], vec![0; 320]);
+        batch_escape_perturbed(&def, &mut references, &offsets, &mut iterations, &mut periods);
+        let secondary = references.secondary.len();
+        assert!(secondary > 0);
+        let mut repeated = vec![0; 320];
+        batch_escape_perturbed(&def, &mut references, &offsets, &mut repeated, &mut periods);
+        assert_eq!(references.secondary.len(), secondary);
+        assert_eq!(repeated, iterations);
+    }
+}
@@ -0,0 +1,344 @@
+use complex::Complex;
+use double_double::DoubleDouble;
+use fixed::Fixed;
+use floatexp::FloatExp;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// The smallest distance between samples for each iteration, relative to the magnitude of
+/// their coordinates, which f32 can position accurately. The rounding errors of f32 grow as the
+/// orbits are iterated, so the samples must be further apart the more iterations there are.
+/// This leaves a wide margin above the precision of f32.
+const SINGLE_MIN_SPACING_PER_ITERATION: f64 = 1e-5;
+/// The smallest distance between samples, relative to the magnitude of their coordinates,
+/// which f64 can position accurately. Below this the samples snap to the nearest f64 values
+/// and the image turns into blocks of identical samples.
+const DOUBLE_MIN_SPACING: f64 = 1e-12;
+/// The smallest distance between samples, relative to the magnitude of their coordinates,
+/// which double-double can position accurately.
+const DOUBLE_DOUBLE_MIN_SPACING: f64 = 1e-28;
+
+/// The precision of the arithmetic used to iterate the samples of a set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    /// f32, which uses a vectorised kernel iterating twice as many points at once as f64. It's
+    /// only accurate enough for views a few thousand samples wide, so it's mostly useful for
+    /// quick previews.
+    Single,
+    /// f64, which uses the vectorised kernel.
+    Double,
+    /// Double-double with about 106 bits of precision, which allows zooming about 10¹⁵ times
+    /// further than f64 but is many times slower.
+    DoubleDouble,
+    /// Fixed-point with as many fractional bits as the distance between samples needs, which
+    /// allows zooming as far as the pixel size can go but is much slower again.
+    Fixed,
+}
+
+impl Precision {
+    /// Returns the precision whose name is `name`, or `None` if there isn't one.
+    pub fn from_name(name: &str) -> Option<Precision> {
+        match name {
+            "single" => Some(Precision::Single),
+            "double" => Some(Precision::Double),
+            "double-double" => Some(Precision::DoubleDouble),
+            "fixed" => Some(Precision::Fixed),
+            _ => None,
+        }
+    }
+
+    /// Returns the lowest precision which can accurately position samples `spacing` apart
+    /// with coordinates up to `magnitude` and iterate them up to `max_iterations` times.
+    pub fn for_spacing(spacing: f64, magnitude: f64, max_iterations: u32) -> Precision {
+        if spacing < magnitude * DOUBLE_DOUBLE_MIN_SPACING {
+            Precision::Fixed
+        } else if spacing < magnitude * DOUBLE_MIN_SPACING {
+            Precision::DoubleDouble
+        } else if spacing < magnitude * SINGLE_MIN_SPACING_PER_ITERATION * max_iterations as f64 {
+            Precision::Double
+        } else {
+            Precision::Single
+        }
+    }
+}
+
+/// The arithmetic needed to calculate the escape iterations of a point, implemented by the
+/// numeric types of each precision.
+pub trait Real: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> {
+    /// The type the orbit points are compared with the escape radius and the periodicity
+    /// tolerance in. Only the orbit points need the full precision, so the types more precise
+    /// than f64 are compared in f64.
+    type Compare: Real + PartialOrd;
+
+    fn from_f64(value: f64) -> Self;
+
+    /// Returns the nearest f64 to the number.
+    fn to_f64(self) -> f64;
+
+    /// Returns the number rounded to the type it's compared in.
+    fn to_compare(self) -> Self::Compare;
+}
+
+impl Real for f64 {
+    type Compare = f64;
+
+    fn from_f64(value: f64) -> f64 {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn to_compare(self) -> f64 {
+        self
+    }
+}
+
+impl Real for f32 {
+    type Compare = f32;
+
+    fn from_f64(value: f64) -> f32 {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn to_compare(self) -> f32 {
+        self
+    }
+}
+
+impl Real for DoubleDouble {
+    type Compare = f64;
+
+    fn from_f64(value: f64) -> DoubleDouble {
+        DoubleDouble::from_f64(value)
+    }
+
+    fn to_f64(self) -> f64 {
+        DoubleDouble::to_f64(self)
+    }
+
+    fn to_compare(self) -> f64 {
+        DoubleDouble::to_f64(self)
+    }
+}
+
+impl Real for FloatExp {
+    type Compare = f64;
+
+    fn from_f64(value: f64) -> FloatExp {
+        FloatExp::from_f64(value)
+    }
+
+    fn to_f64(self) -> f64 {
+        FloatExp::to_f64(self)
+    }
+
+    fn to_compare(self) -> f64 {
+        FloatExp::to_f64(self)
+    }
+}
+
+impl Real for Fixed {
+    type Compare = f64;
+
+    fn from_f64(value: f64) -> Fixed {
+        Fixed::from_f64(value)
+    }
+
+    fn to_f64(self) -> f64 {
+        Fixed::to_f64(self)
+    }
+
+    fn to_compare(self) -> f64 {
+        Fixed::to_f64(self)
+    }
+}
+
+/// The division and elementary functions of the hardware floating point types, which complex
+/// numbers and vectors are generic over.
+pub trait Float: Real + Div<Output = Self> + Neg<Output = Self> + PartialOrd {
+    fn sqrt(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn atan2(self, other: Self) -> Self;
+    fn powf(self, n: Self) -> Self;
+}
+
+macro_rules! impl_float {
+    ($float:ident) => {
+        impl Float for $float {
+            fn sqrt(self) -> $float {
+                $float::sqrt(self)
+            }
+
+            fn exp(self) -> $float {
+                $float::exp(self)
+            }
+
+            fn ln(self) -> $float {
+                $float::ln(self)
+            }
+
+            fn sin(self) -> $float {
+                $float::sin(self)
+            }
+
+            fn cos(self) -> $float {
+                $float::cos(self)
+            }
+
+            fn atan2(self, other: $float) -> $float {
+                $float::atan2(self, other)
+            }
+
+            fn powf(self, n: $float) -> $float {
+                $float::powf(self, n)
+            }
+        }
+    };
+}
+
+impl_float!(f32);
+impl_float!(f64);
+
+/// Returns the escape iterations of `point` along with the period of the cycle its orbit is
+/// attracted to, calculated in the precision of `T`. The period is only checked if `tolerance`
+/// isn't `None`. This is the kernel every precision iterates its points with one at a time.
+///
+/// Cycles are found using Brent's algorithm. An orbit point is saved at iterations which are
+/// powers of two and each following point is compared with it. If a point comes within
+/// `tolerance` of the saved point the orbit is treated as a cycle and the point as in the set.
+/// The number of iterations since the point was saved is the period of the cycle, or a
+/// multiple of it. Orbits which land almost exactly on a repelling cycle, such as the orbit
+/// of the tip of the antenna at -2, can be mistaken for attracting cycles.
+///
+/// The comparisons are made in `T::Compare`, with the escape value and the tolerance rounded
+/// to it, so the results in f32 and f64 are identical to the vectorised kernels.
+pub fn escape_iterations<T: Real>(point: Complex<T>, max_iterations: u32, escape_radius: f64, tolerance: Option<f64>) -> (u32, u32) {
+    let escape_value = T::Compare::from_f64(escape_radius * escape_radius);
+    let tolerance_value = tolerance.map(|tolerance| T::Compare::from_f64(tolerance * tolerance));
+    let mut z = point;
+    let mut saved = z;
+    let mut save_iter = 0;
+    let mut next_save = 1u32;
+
+    for i in 0..max_iterations {
+        // it's more efficient to explode the complex into real and imaginary parts rather
+        // than multiplying the Complex. this way the squares only need to be calculated once
+        // and the square root can be avoided altogether
+        let zr2 = z.real * z.real;
+        let zi2 = z.imag * z.imag;
+        let zri = z.real * z.imag;
+
+        if (zr2 + zi2).to_compare() > escape_value {
+            return (i, 0);
+        }
+        z = Complex::new(zr2 - zi2 + point.real, zri + zri + point.imag);
+
+        if let Some(tolerance_value) = tolerance_value {
+            let dr = (z.real - saved.real).to_compare();
+            let di = (z.imag - saved.imag).to_compare();
+
+            if dr * dr + di * di < tolerance_value {
+                return (0, i + 1 - save_iter);
+            }
+            if i + 1 == next_save {
+                saved = z;
+                save_iter = i + 1;
+                next_save = next_save.saturating_mul(2);
+            }
+        }
+    }
+    (0, 0)
+}
+
+/// Returns true if the point is inside the main cardioid or the period-2 bulb, which make up
+/// most of the area of the set. These points never escape so there is no need to iterate them.
+/// The check is made in the precision of `T` so points near the boundaries are classified
+/// correctly at any zoom.
+pub fn in_cardioid_or_bulb<T: Real>(point: Complex<T>) -> bool {
+    let x = point.real - T::from_f64(0.25);
+    let y2 = point.imag * point.imag;
+    let q = x * x + y2;
+    let in_cardioid = (q * (q + x) - T::from_f64(0.25) * y2).to_f64() <= 0.0;
+    let real_plus_1 = point.real + T::from_f64(1.0);
+    let in_bulb = (real_plus_1 * real_plus_1 + y2 - T::from_f64(0.0625)).to_f64() <= 0.0;
+    in_cardioid || in_bulb
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simd;
+
+    #[test]
+    fn for_spacing() {
+        assert_eq!(Precision::for_spacing(0.01, 2.0, 100), Precision::Single);
+        assert_eq!(Precision::for_spacing(1e-3, 2.0, 100), Precision::Double);
+        assert_eq!(Precision::for_spacing(1e-6, 2.0, 100), Precision::Double);
+        assert_eq!(Precision::for_spacing(1e-11, 2.0, 100), Precision::Double);
+        assert_eq!(Precision::for_spacing(1e-13, 2.0, 100), Precision::DoubleDouble);
+        assert_eq!(Precision::for_spacing(1e-29, 2.0, 100), Precision::Fixed);
+        assert_eq!(Precision::for_spacing(1e-100, 2.0, 100), Precision::Fixed);
+        // small coordinates near the origin can be positioned in f64 at deeper zooms
+        assert_eq!(Precision::for_spacing(1e-18, 1e-7, 100), Precision::Double);
+        // the rounding errors of f32 grow with the iterations
+        assert_eq!(Precision::for_spacing(0.01, 2.0, 1000), Precision::Double);
+    }
+
+    #[test]
+    fn double_matches_vectorised_kernel() {
+        let points = (0..200)
+            .map(|i| Complex::new(-2.0 + 0.0151 * i as f64, 1.1 - 0.0107 * i as f64))
+            .collect::<Vec<_>>();
+        let mut iterations = vec![0; points.len()];
+        simd::escape_iterations_row(&points, 500, 2.0, &mut iterations);
+
+        for (&point, &iters) in points.iter().zip(&iterations) {
+            assert_eq!(escape_iterations(point, 500, 2.0, None), (iters, 0));
+        }
+    }
+
+    #[test]
+    fn double_double_matches_double() {
+        // away from the boundary of the set the extra precision doesn't change the results
+        for i in 0..200 {
+            let point = Complex::new(-2.0 + 0.0151 * i as f64, 1.1 - 0.0107 * i as f64);
+            let dd_point = Complex::<DoubleDouble>::from_complex(point);
+            let (iterations, _) = escape_iterations(point, 100, 2.0, None);
+            assert_eq!(escape_iterations(dd_point, 100, 2.0, None).0, iterations);
+            assert_eq!(in_cardioid_or_bulb(dd_point), in_cardioid_or_bulb(point));
+        }
+    }
+
+    #[test]
+    fn fixed_matches_double() {
+        for i in 0..200 {
+            let point = Complex::new(-2.0 + 0.0151 * i as f64, 1.1 - 0.0107 * i as f64);
+            let fixed_point = Complex::new(Fixed::from_f64(point.real).with_limbs(6), Fixed::from_f64(point.imag).with_limbs(6));
+            assert_eq!(escape_iterations(fixed_point, 100, 2.0, Some(1e-6)).0, escape_iterations(point, 100, 2.0, Some(1e-6)).0);
+            assert_eq!(in_cardioid_or_bulb(fixed_point), in_cardioid_or_bulb(point));
+        }
+    }
+
+    #[test]
+    fn double_double_resolves_deep_points() {
+        // points 10⁻²⁰ apart near the boundary of the set are the same point in f64 but
+        // escape after different numbers of iterations in double-double
+        let centre = Fixed::parse("-0.743643887037158704752191506114774").unwrap().to_dd();
+        let imag = Fixed::parse("0.131825904205311970493132056385139").unwrap().to_dd();
+        let iterations = (1..20)
+            .map(|i| escape_iterations(Complex::new(centre + DoubleDouble::from_f64(i as f64 * 1e-20), imag), 10_000, 2.0, None).0)
+            .collect::<Vec<_>>();
+        assert!(iterations.windows(2).any(|pair| pair[0] != pair[1]), "{:?}", iterations);
+        assert!(iterations.iter().all(|&iters| iters != 0));
+    }
+}
@@ -0,0 +1,139 @@
+use complex::Complex;
+use floatexp::FeComplex;
+use perturbation::GLITCH_TOLERANCE;
+
+/// The number of terms of the series, which are the powers of the delta from the reference
+/// point from the first up.
+const TERMS: usize = 8;
+/// The largest error of the series at the corners of a set, relative to the deltas found by
+/// iterating them, for the series to be used.
+const TOLERANCE: f64 = 1e-12;
+
+/// A series in the delta of a point from a reference point which approximates the delta of
+/// the point's orbit from the reference orbit after a number of iterations. The iterations it
+/// covers are skipped for every point of a set at once.
+///
+/// The coefficients are found from the reference orbit by substituting the series into the
+/// perturbed iteration. They are `FloatExp`s because the nth coefficient grows like the nth
+/// power of the first, which overflows f64 in deep zooms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesApproximation {
+    /// The number of iterations skipped, which is the index in the reference orbit of the orbit
+    /// point the series approximates.
+    skipped: usize,
+    coefficients: [FeComplex; TERMS],
+}
+
+impl SeriesApproximation {
+    /// Finds the series for a set whose corners have the deltas `corners` from the reference
+    /// point with the orbit `orbit`, or `None` if it doesn't skip any iterations.
+    ///
+    /// The series is extended one iteration at a time while it stays within the tolerance of
+    /// the deltas found by iterating the corners. The points inside the set are closer to the
+    /// reference than the corners, so the series is more accurate for them. The corners mustn't
+    /// escape or be glitched in the skipped iterations, because those iterations aren't checked
+    /// for the other points.
+    pub fn new(orbit: &[Complex], corners: &[FeComplex], escape_value: f64) -> Option<SeriesApproximation> {
+        let one = FeComplex::from_complex(Complex::new(1.0, 0.0));
+        let zero = FeComplex::from_complex(Complex::new(0.0, 0.0));
+        let mut coefficients = [zero; TERMS];
+        coefficients[0] = one;
+        let mut series = SeriesApproximation { skipped: 0, coefficients };
+        let mut deltas = corners.to_vec();
+
+        for (n, &reference) in orbit.iter().enumerate().take(orbit.len().saturating_sub(1)) {
+            let unusable = deltas.iter().any(|&delta| {
+                let z = reference + delta.to_complex();
+                z.norm_sqr() > escape_value || z.norm_sqr() < GLITCH_TOLERANCE * GLITCH_TOLERANCE * reference.norm_sqr()
+            });
+            if unusable {
+                break;
+            }
+            let two_z = FeComplex::from_complex(Complex::new(2.0 * reference.real, 2.0 * reference.imag));
+
+            for (delta, &delta_c) in deltas.iter_mut().zip(corners) {
+                *delta = two_z * *delta + *delta * *delta + delta_c;
+            }
+            // the coefficient of each power of δc is 2Z times itself plus the products of the
+            // pairs of coefficients whose powers add up to it, and δc adds one to the first
+            let mut next = [zero; TERMS];
+
+            for (k, coefficient) in next.iter_mut().enumerate() {
+                *coefficient = (0..k).fold(two_z * coefficients[k], |sum, i| sum + coefficients[i] * coefficients[k - 1 - i]);
+            }
+            next[0] = next[0] + one;
+            coefficients = next;
+            let candidate = SeriesApproximation { skipped: n + 1, coefficients };
+            let accurate = deltas.iter().zip(corners).all(|(&delta, &delta_c)| {
+                let error = candidate.delta(delta_c) - delta;
+                (error.norm_sqr() / delta.norm_sqr()).to_f64() <= TOLERANCE * TOLERANCE
+            });
+            if !accurate {
+                break;
+            }
+            series = candidate;
+        }
+        if series.skipped == 0 { None } else { Some(series) }
+    }
+
+    /// Returns the number of iterations skipped.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Returns the delta from the reference orbit after the skipped iterations of the point
+    /// `delta_c` from the reference point.
+    pub fn delta(&self, delta_c: FeComplex) -> FeComplex {
+        self.coefficients.iter().rev()
+            .fold(FeComplex::from_complex(Complex::new(0.0, 0.0)), |sum, &coefficient| (sum + coefficient) * delta_c)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coefficients() {
+        // with a reference orbit staying at zero, δₙ₊₁ = δₙ² + δc, so the series matches the
+        // first three deltas exactly, but the fourth has powers of δc up to the sixteenth
+        let orbit = vec![Complex::new(0.0, 0.0); 10];
+        let delta_c = FeComplex::from_complex(Complex::new(0.1, 0.0));
+        let series = SeriesApproximation::new(&orbit, &[delta_c], 4.0).unwrap();
+        assert_eq!(series.skipped(), 3);
+        let delta = (0..3).fold(0.1, |delta: f64, _| delta * delta + 0.1);
+        assert!((series.delta(delta_c).to_complex() - Complex::new(delta, 0.0)).abs() < 1e-15);
+        // the higher powers are negligible for a smaller delta
+        let series = SeriesApproximation::new(&orbit, &[FeComplex::from_complex(Complex::new(1e-5, 0.0))], 4.0).unwrap();
+        assert_eq!(series.skipped(), 9);
+    }
+
+    #[test]
+    fn matches_iterated_deltas() {
+        // the series for the corners of a square around a point in the seahorse valley is
+        // accurate for the points inside it
+        let centre = Complex::new(-0.743643887, 0.131825904);
+        let mut orbit = Vec::new();
+        let mut z = centre;
+
+        for _ in 0..200 {
+            orbit.push(z);
+            z = z * z + centre;
+        }
+        let corner = |real: f64, imag: f64| FeComplex::from_complex(Complex::new(real, imag));
+        let corners = [corner(-1e-9, -1e-9), corner(1e-9, -1e-9), corner(-1e-9, 1e-9), corner(1e-9, 1e-9)];
+        let series = SeriesApproximation::new(&orbit, &corners, 4.0).unwrap();
+        assert!(series.skipped() > 10);
+
+        let delta_c = Complex::new(3e-10, -7e-10);
+        let mut delta = delta_c;
+
+        for &reference in &orbit[..series.skipped()] {
+            delta = Complex::new(2.0, 0.0) * reference * delta + delta * delta + delta_c;
+        }
+        let approximated = series.delta(FeComplex::from_complex(delta_c)).to_complex();
+        assert!((approximated - delta).abs() < 1e-6 * delta.abs());
+    }
+}
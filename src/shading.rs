@@ -0,0 +1,99 @@
+use complex::Complex;
+use vector3d::Vector3d;
+
+/// The direction the image is viewed from, straight down onto the complex plane.
+const VIEW: Vector3d = Vector3d { x: 0.0, y: 0.0, z: 1.0 };
+
+/// Lighting used to shade the outside of the set as if it were a surface, making the set
+/// look embossed.
+///
+/// The surface normal at a point is derived from the gradient of the distance estimate, which
+/// points along z / z' where z' is the derivative of z with respect to c. The light is a
+/// directional light and the surface is lit using the Blinn-Phong model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lighting {
+    /// Direction pointing towards the light.
+    pub direction: Vector3d,
+    /// The height of the normal relative to its horizontal component, the larger the value
+    /// the flatter the surface appears.
+    pub height: f64,
+    pub ambient: f64,
+    pub diffuse: f64,
+    pub specular: f64,
+    pub shininess: f64,
+}
+
+impl Lighting {
+    /// Creates lighting with a light at the specified azimuth and elevation in degrees. The
+    /// azimuth is measured anticlockwise from the positive real axis.
+    pub fn new(azimuth: f64, elevation: f64) -> Lighting {
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        let direction = Vector3d::new(
+            azimuth.cos() * elevation.cos(),
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+        );
+        Lighting { direction, height: 1.0, ambient: 0.2, diffuse: 0.8, specular: 0.3, shininess: 20.0 }
+    }
+
+    /// Parses lighting from a string containing the azimuth and elevation separated by a comma,
+    /// for example "45,30".
+    pub fn parse(value: &str) -> Option<Lighting> {
+        let mut angles = value.split(',').map(|angle| angle.trim().parse::<f64>());
+
+        match (angles.next(), angles.next(), angles.next()) {
+            (Some(Ok(azimuth)), Some(Ok(elevation)), None) => Some(Lighting::new(azimuth, elevation)),
+            _ => None,
+        }
+    }
+
+    /// Returns the factor by which the colour of a point outside the set is multiplied, given
+    /// the final value of `z` and its derivative with respect to c.
+    pub fn shade(&self, z: Complex, dz: Complex) -> f64 {
+        let u = z / dz;
+        let norm = u.abs();
+
+        if norm == 0.0 || !norm.is_finite() {
+            return 1.0;
+        }
+        let normal = Vector3d::new(u.real / norm, u.imag / norm, self.height).normalise();
+        let diffuse = normal.dot(&self.direction).max(0.0);
+        let half = (self.direction + VIEW).normalise();
+        let specular = normal.dot(&half).max(0.0).powf(self.shininess);
+        self.ambient + self.diffuse * diffuse + self.specular * specular
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let lighting = Lighting::parse("90, 0").unwrap();
+        assert!(lighting.direction.x.abs() < 1e-15);
+        assert!((lighting.direction.y - 1.0).abs() < 1e-15);
+        assert!(lighting.direction.z.abs() < 1e-15);
+        assert_eq!(Lighting::parse("90"), None);
+        assert_eq!(Lighting::parse("90,0,1"), None);
+        assert_eq!(Lighting::parse("up,0"), None);
+    }
+
+    #[test]
+    fn surface_facing_light_is_brighter() {
+        let lighting = Lighting::new(0.0, 45.0);
+        let dz = Complex::new(1.0, 0.0);
+        // the normal tilts towards the direction of z / z'
+        let towards = lighting.shade(Complex::new(1.0, 0.0), dz);
+        let away = lighting.shade(Complex::new(-1.0, 0.0), dz);
+        assert!(towards > away);
+    }
+
+    #[test]
+    fn zero_derivative_is_unshaded() {
+        let lighting = Lighting::new(0.0, 45.0);
+        assert_eq!(lighting.shade(Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)), 1.0);
+    }
+}
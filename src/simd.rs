@@ -0,0 +1,721 @@
+use complex::Complex;
+use precision::{self, Real};
+
+/// Returns the number of points the vectorised kernel iterates at once on this CPU, or 1 if
+/// there is no vectorised kernel and the scalar kernel is used.
+pub fn lanes() -> usize {
+    arch::lanes()
+}
+
+/// Calculates the escape iterations for each point in `points` and writes them into `iterations`,
+/// which must be the same length. Zero is written for points in the set.
+///
+/// The points are iterated in groups using the widest vector instructions supported by the CPU.
+/// The arithmetic is performed in the same order as `precision::escape_iterations` and without
+/// fused multiply-adds, so the results are identical to calculating the points one at a time.
+pub fn escape_iterations_row(points: &[Complex], max_iterations: u32, escape_radius: f64, iterations: &mut [u32]) {
+    assert_eq!(points.len(), iterations.len());
+    arch::escape_iterations_row(points, max_iterations, escape_radius, None, iterations, None);
+}
+
+/// Calculates the escape iterations for each point in `points` like `escape_iterations_row`,
+/// using periodicity checking to stop iterating points whose orbits are attracted to a cycle.
+///
+/// The period of each cycle found is written into `periods`, zero is written for points where
+/// no cycle was found. The results are identical to `precision::escape_iterations` in f64.
+pub fn escape_iterations_row_periodic(
+    points: &[Complex],
+    max_iterations: u32,
+    escape_radius: f64,
+    tolerance: f64,
+    iterations: &mut [u32],
+    periods: &mut [u32],
+) {
+    assert_eq!(points.len(), iterations.len());
+    assert_eq!(points.len(), periods.len());
+    arch::escape_iterations_row(points, max_iterations, escape_radius, Some(tolerance), iterations, Some(periods));
+}
+
+/// Returns the number of points the vectorised f32 kernel iterates at once on this CPU, or 1 if
+/// there is no vectorised kernel and the scalar kernel is used.
+pub fn single_lanes() -> usize {
+    arch::single_lanes()
+}
+
+/// Calculates the escape iterations for each point in `points` like `escape_iterations_row`,
+/// but in f32. This is about twice as fast as f64 because twice as many points fit in each
+/// vector, but it's only accurate if the points are far enough apart.
+pub fn escape_iterations_row_single(points: &[Complex], max_iterations: u32, escape_radius: f64, iterations: &mut [u32]) {
+    assert_eq!(points.len(), iterations.len());
+    arch::escape_iterations_row_single(points, max_iterations, escape_radius, None, iterations, None);
+}
+
+/// Calculates the escape iterations and periods for each point in `points` like
+/// `escape_iterations_row_periodic`, but in f32.
+pub fn escape_iterations_row_single_periodic(
+    points: &[Complex],
+    max_iterations: u32,
+    escape_radius: f64,
+    tolerance: f64,
+    iterations: &mut [u32],
+    periods: &mut [u32],
+) {
+    assert_eq!(points.len(), iterations.len());
+    assert_eq!(points.len(), periods.len());
+    arch::escape_iterations_row_single(points, max_iterations, escape_radius, Some(tolerance), iterations, Some(periods));
+}
+
+/// Calculates the escape iterations of the points one at a time in the precision of `T`, which
+/// gives the same results as the vectorised kernels in f64 and f32.
+fn escape_iterations_scalar<T: Real>(
+    points: &[Complex],
+    max_iterations: u32,
+    escape_radius: f64,
+    tolerance: Option<f64>,
+    iterations: &mut [u32],
+    mut periods: Option<&mut [u32]>,
+) {
+    for (idx, &point) in points.iter().enumerate() {
+        let (iters, period) = precision::escape_iterations(Complex::<T>::from_complex(point), max_iterations, escape_radius, tolerance);
+        iterations[idx] = iters;
+
+        if let Some(ref mut periods) = periods {
+            periods[idx] = period;
+        }
+    }
+}
+
+/// Splits `points` into groups of `N` points and passes each group to `kernel` along with the
+/// arrays its iterations and periods are written to. The last group is padded by repeating its
+/// last point.
+fn for_each_group<const N: usize, F>(points: &[Complex], iterations: &mut [u32], mut periods: Option<&mut [u32]>, mut kernel: F)
+    where F: FnMut(&[Complex; N], &mut [u32; N], &mut [u32; N]) {
+    for (idx, group) in points.chunks(N).enumerate() {
+        let (start, len) = (idx * N, group.len());
+        let mut padded = [group[len - 1]; N];
+        padded[..len].copy_from_slice(group);
+        let mut padded_iters = [0; N];
+        let mut padded_periods = [0; N];
+        kernel(&padded, &mut padded_iters, &mut padded_periods);
+        iterations[start..start + len].copy_from_slice(&padded_iters[..len]);
+
+        if let Some(ref mut periods) = periods {
+            periods[start..start + len].copy_from_slice(&padded_periods[..len]);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use complex::Complex;
+    use std::arch::x86_64::*;
+    use super::{escape_iterations_scalar, for_each_group};
+
+    pub fn lanes() -> usize {
+        if is_x86_feature_detected!("avx512f") {
+            8
+        } else if is_x86_feature_detected!("avx") {
+            4
+        } else {
+            // SSE2 is always available on x86_64
+            2
+        }
+    }
+
+    pub fn single_lanes() -> usize {
+        lanes() * 2
+    }
+
+    pub fn escape_iterations_row(
+        points: &[Complex],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: Option<f64>,
+        iterations: &mut [u32],
+        periods: Option<&mut [u32]>,
+    ) {
+        let periodic = tolerance.is_some();
+        let tolerance = tolerance.unwrap_or(0.0);
+        // the unsafe blocks are sound because the kernels are only called if the CPU supports
+        // the instructions they are compiled for
+        match (lanes(), periodic) {
+            (8, false) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_avx512::<false>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (8, true) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_avx512::<true>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (4, false) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_avx::<false>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (4, true) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_avx::<true>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (2, false) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_sse2::<false>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (2, true) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_sse2::<true>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            _ => {
+                let tolerance = if periodic { Some(tolerance) } else { None };
+                escape_iterations_scalar::<f64>(points, max_iterations, escape_radius, tolerance, iterations, periods)
+            }
+        }
+    }
+
+    pub fn escape_iterations_row_single(
+        points: &[Complex],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: Option<f64>,
+        iterations: &mut [u32],
+        periods: Option<&mut [u32]>,
+    ) {
+        let periodic = tolerance.is_some();
+        let tolerance = tolerance.unwrap_or(0.0);
+        // the unsafe blocks are sound because the kernels are only called if the CPU supports
+        // the instructions they are compiled for
+        match (single_lanes(), periodic) {
+            (16, false) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_single_avx512::<false>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (16, true) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_single_avx512::<true>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (8, false) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_single_avx::<false>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (8, true) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_single_avx::<true>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (4, false) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_single_sse2::<false>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            (4, true) => for_each_group(points, iterations, periods, |group, iters, periods| unsafe {
+                escape_iterations_single_sse2::<true>(group, max_iterations, escape_radius, tolerance, iters, periods)
+            }),
+            _ => {
+                let tolerance = if periodic { Some(tolerance) } else { None };
+                escape_iterations_scalar::<f32>(points, max_iterations, escape_radius, tolerance, iterations, periods)
+            }
+        }
+    }
+
+    /// Records `i` as the escape iterations of the lanes which have escaped for the first time
+    /// and returns the lanes which are still active.
+    fn record_escapes(escaped: u32, active: u32, i: u32, iters: &mut [u32]) -> u32 {
+        let mut newly_escaped = escaped & active;
+
+        while newly_escaped != 0 {
+            let lane = newly_escaped.trailing_zeros();
+            iters[lane as usize] = i;
+            newly_escaped &= newly_escaped - 1;
+        }
+        active & !escaped
+    }
+
+    /// Records `period` for the active lanes whose orbits have returned to the saved point and
+    /// returns the lanes which are still active.
+    fn record_periods(returned: u32, active: u32, period: u32, periods: &mut [u32]) -> u32 {
+        let mut newly_returned = returned & active;
+
+        while newly_returned != 0 {
+            let lane = newly_returned.trailing_zeros();
+            periods[lane as usize] = period;
+            newly_returned &= newly_returned - 1;
+        }
+        active & !returned
+    }
+
+    // The kernels below all follow the same steps as `precision::escape_iterations`, using the
+    // same schedule for saving the orbit point so the lanes can share it.
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn escape_iterations_avx512<const PERIODIC: bool>(
+        points: &[Complex; 8],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: f64,
+        iters: &mut [u32; 8],
+        periods: &mut [u32; 8],
+    ) {
+        let real = |i: usize| points[i].real;
+        let imag = |i: usize| points[i].imag;
+        let c_real = _mm512_setr_pd(real(0), real(1), real(2), real(3), real(4), real(5), real(6), real(7));
+        let c_imag = _mm512_setr_pd(imag(0), imag(1), imag(2), imag(3), imag(4), imag(5), imag(6), imag(7));
+        let escape_value = _mm512_set1_pd(escape_radius * escape_radius);
+        let tolerance_value = _mm512_set1_pd(tolerance * tolerance);
+        let (mut z_real, mut z_imag) = (c_real, c_imag);
+        let (mut saved_real, mut saved_imag) = (z_real, z_imag);
+        let (mut save_iter, mut next_save) = (0, 1u32);
+        let mut active = 0xff;
+
+        for i in 0..max_iterations {
+            let zr2 = _mm512_mul_pd(z_real, z_real);
+            let zi2 = _mm512_mul_pd(z_imag, z_imag);
+            let zri = _mm512_mul_pd(z_real, z_imag);
+            let escaped = _mm512_cmp_pd_mask::<_CMP_GT_OQ>(_mm512_add_pd(zr2, zi2), escape_value) as u32;
+            active = record_escapes(escaped, active, i, iters);
+
+            if active == 0 {
+                return;
+            }
+            z_real = _mm512_add_pd(_mm512_sub_pd(zr2, zi2), c_real);
+            z_imag = _mm512_add_pd(_mm512_add_pd(zri, zri), c_imag);
+
+            if PERIODIC {
+                let d_real = _mm512_sub_pd(z_real, saved_real);
+                let d_imag = _mm512_sub_pd(z_imag, saved_imag);
+                let dist = _mm512_add_pd(_mm512_mul_pd(d_real, d_real), _mm512_mul_pd(d_imag, d_imag));
+                let returned = _mm512_cmp_pd_mask::<_CMP_LT_OQ>(dist, tolerance_value) as u32;
+                active = record_periods(returned, active, i + 1 - save_iter, periods);
+
+                if active == 0 {
+                    return;
+                }
+                if i + 1 == next_save {
+                    saved_real = z_real;
+                    saved_imag = z_imag;
+                    save_iter = i + 1;
+                    next_save = next_save.saturating_mul(2);
+                }
+            }
+        }
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn escape_iterations_avx<const PERIODIC: bool>(
+        points: &[Complex; 4],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: f64,
+        iters: &mut [u32; 4],
+        periods: &mut [u32; 4],
+    ) {
+        let c_real = _mm256_setr_pd(points[0].real, points[1].real, points[2].real, points[3].real);
+        let c_imag = _mm256_setr_pd(points[0].imag, points[1].imag, points[2].imag, points[3].imag);
+        let escape_value = _mm256_set1_pd(escape_radius * escape_radius);
+        let tolerance_value = _mm256_set1_pd(tolerance * tolerance);
+        let (mut z_real, mut z_imag) = (c_real, c_imag);
+        let (mut saved_real, mut saved_imag) = (z_real, z_imag);
+        let (mut save_iter, mut next_save) = (0, 1u32);
+        let mut active = 0xf;
+
+        for i in 0..max_iterations {
+            let zr2 = _mm256_mul_pd(z_real, z_real);
+            let zi2 = _mm256_mul_pd(z_imag, z_imag);
+            let zri = _mm256_mul_pd(z_real, z_imag);
+            let escaped = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_GT_OQ>(_mm256_add_pd(zr2, zi2), escape_value)) as u32;
+            active = record_escapes(escaped, active, i, iters);
+
+            if active == 0 {
+                return;
+            }
+            z_real = _mm256_add_pd(_mm256_sub_pd(zr2, zi2), c_real);
+            z_imag = _mm256_add_pd(_mm256_add_pd(zri, zri), c_imag);
+
+            if PERIODIC {
+                let d_real = _mm256_sub_pd(z_real, saved_real);
+                let d_imag = _mm256_sub_pd(z_imag, saved_imag);
+                let dist = _mm256_add_pd(_mm256_mul_pd(d_real, d_real), _mm256_mul_pd(d_imag, d_imag));
+                let returned = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_LT_OQ>(dist, tolerance_value)) as u32;
+                active = record_periods(returned, active, i + 1 - save_iter, periods);
+
+                if active == 0 {
+                    return;
+                }
+                if i + 1 == next_save {
+                    saved_real = z_real;
+                    saved_imag = z_imag;
+                    save_iter = i + 1;
+                    next_save = next_save.saturating_mul(2);
+                }
+            }
+        }
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn escape_iterations_sse2<const PERIODIC: bool>(
+        points: &[Complex; 2],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: f64,
+        iters: &mut [u32; 2],
+        periods: &mut [u32; 2],
+    ) {
+        let c_real = _mm_setr_pd(points[0].real, points[1].real);
+        let c_imag = _mm_setr_pd(points[0].imag, points[1].imag);
+        let escape_value = _mm_set1_pd(escape_radius * escape_radius);
+        let tolerance_value = _mm_set1_pd(tolerance * tolerance);
+        let (mut z_real, mut z_imag) = (c_real, c_imag);
+        let (mut saved_real, mut saved_imag) = (z_real, z_imag);
+        let (mut save_iter, mut next_save) = (0, 1u32);
+        let mut active = 0x3;
+
+        for i in 0..max_iterations {
+            let zr2 = _mm_mul_pd(z_real, z_real);
+            let zi2 = _mm_mul_pd(z_imag, z_imag);
+            let zri = _mm_mul_pd(z_real, z_imag);
+            let escaped = _mm_movemask_pd(_mm_cmpgt_pd(_mm_add_pd(zr2, zi2), escape_value)) as u32;
+            active = record_escapes(escaped, active, i, iters);
+
+            if active == 0 {
+                return;
+            }
+            z_real = _mm_add_pd(_mm_sub_pd(zr2, zi2), c_real);
+            z_imag = _mm_add_pd(_mm_add_pd(zri, zri), c_imag);
+
+            if PERIODIC {
+                let d_real = _mm_sub_pd(z_real, saved_real);
+                let d_imag = _mm_sub_pd(z_imag, saved_imag);
+                let dist = _mm_add_pd(_mm_mul_pd(d_real, d_real), _mm_mul_pd(d_imag, d_imag));
+                let returned = _mm_movemask_pd(_mm_cmplt_pd(dist, tolerance_value)) as u32;
+                active = record_periods(returned, active, i + 1 - save_iter, periods);
+
+                if active == 0 {
+                    return;
+                }
+                if i + 1 == next_save {
+                    saved_real = z_real;
+                    saved_imag = z_imag;
+                    save_iter = i + 1;
+                    next_save = next_save.saturating_mul(2);
+                }
+            }
+        }
+    }
+
+    // The f32 kernels follow the same steps again with the escape value and tolerance rounded
+    // to f32, like `precision::escape_iterations` in f32.
+
+    #[target_feature(enable = "avx512f")]
+    unsafe fn escape_iterations_single_avx512<const PERIODIC: bool>(
+        points: &[Complex; 16],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: f64,
+        iters: &mut [u32; 16],
+        periods: &mut [u32; 16],
+    ) {
+        let real: [f32; 16] = std::array::from_fn(|i| points[i].real as f32);
+        let imag: [f32; 16] = std::array::from_fn(|i| points[i].imag as f32);
+        let c_real = _mm512_loadu_ps(real.as_ptr());
+        let c_imag = _mm512_loadu_ps(imag.as_ptr());
+        let escape_value = _mm512_set1_ps((escape_radius * escape_radius) as f32);
+        let tolerance_value = _mm512_set1_ps((tolerance * tolerance) as f32);
+        let (mut z_real, mut z_imag) = (c_real, c_imag);
+        let (mut saved_real, mut saved_imag) = (z_real, z_imag);
+        let (mut save_iter, mut next_save) = (0, 1u32);
+        let mut active = 0xffff;
+
+        for i in 0..max_iterations {
+            let zr2 = _mm512_mul_ps(z_real, z_real);
+            let zi2 = _mm512_mul_ps(z_imag, z_imag);
+            let zri = _mm512_mul_ps(z_real, z_imag);
+            let escaped = _mm512_cmp_ps_mask::<_CMP_GT_OQ>(_mm512_add_ps(zr2, zi2), escape_value) as u32;
+            active = record_escapes(escaped, active, i, iters);
+
+            if active == 0 {
+                return;
+            }
+            z_real = _mm512_add_ps(_mm512_sub_ps(zr2, zi2), c_real);
+            z_imag = _mm512_add_ps(_mm512_add_ps(zri, zri), c_imag);
+
+            if PERIODIC {
+                let d_real = _mm512_sub_ps(z_real, saved_real);
+                let d_imag = _mm512_sub_ps(z_imag, saved_imag);
+                let dist = _mm512_add_ps(_mm512_mul_ps(d_real, d_real), _mm512_mul_ps(d_imag, d_imag));
+                let returned = _mm512_cmp_ps_mask::<_CMP_LT_OQ>(dist, tolerance_value) as u32;
+                active = record_periods(returned, active, i + 1 - save_iter, periods);
+
+                if active == 0 {
+                    return;
+                }
+                if i + 1 == next_save {
+                    saved_real = z_real;
+                    saved_imag = z_imag;
+                    save_iter = i + 1;
+                    next_save = next_save.saturating_mul(2);
+                }
+            }
+        }
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn escape_iterations_single_avx<const PERIODIC: bool>(
+        points: &[Complex; 8],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: f64,
+        iters: &mut [u32; 8],
+        periods: &mut [u32; 8],
+    ) {
+        let real: [f32; 8] = std::array::from_fn(|i| points[i].real as f32);
+        let imag: [f32; 8] = std::array::from_fn(|i| points[i].imag as f32);
+        let c_real = _mm256_loadu_ps(real.as_ptr());
+        let c_imag = _mm256_loadu_ps(imag.as_ptr());
+        let escape_value = _mm256_set1_ps((escape_radius * escape_radius) as f32);
+        let tolerance_value = _mm256_set1_ps((tolerance * tolerance) as f32);
+        let (mut z_real, mut z_imag) = (c_real, c_imag);
+        let (mut saved_real, mut saved_imag) = (z_real, z_imag);
+        let (mut save_iter, mut next_save) = (0, 1u32);
+        let mut active = 0xff;
+
+        for i in 0..max_iterations {
+            let zr2 = _mm256_mul_ps(z_real, z_real);
+            let zi2 = _mm256_mul_ps(z_imag, z_imag);
+            let zri = _mm256_mul_ps(z_real, z_imag);
+            let escaped = _mm256_movemask_ps(_mm256_cmp_ps::<_CMP_GT_OQ>(_mm256_add_ps(zr2, zi2), escape_value)) as u32;
+            active = record_escapes(escaped, active, i, iters);
+
+            if active == 0 {
+                return;
+            }
+            z_real = _mm256_add_ps(_mm256_sub_ps(zr2, zi2), c_real);
+            z_imag = _mm256_add_ps(_mm256_add_ps(zri, zri), c_imag);
+
+            if PERIODIC {
+                let d_real = _mm256_sub_ps(z_real, saved_real);
+                let d_imag = _mm256_sub_ps(z_imag, saved_imag);
+                let dist = _mm256_add_ps(_mm256_mul_ps(d_real, d_real), _mm256_mul_ps(d_imag, d_imag));
+                let returned = _mm256_movemask_ps(_mm256_cmp_ps::<_CMP_LT_OQ>(dist, tolerance_value)) as u32;
+                active = record_periods(returned, active, i + 1 - save_iter, periods);
+
+                if active == 0 {
+                    return;
+                }
+                if i + 1 == next_save {
+                    saved_real = z_real;
+                    saved_imag = z_imag;
+                    save_iter = i + 1;
+                    next_save = next_save.saturating_mul(2);
+                }
+            }
+        }
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn escape_iterations_single_sse2<const PERIODIC: bool>(
+        points: &[Complex; 4],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: f64,
+        iters: &mut [u32; 4],
+        periods: &mut [u32; 4],
+    ) {
+        let real = |i: usize| points[i].real as f32;
+        let imag = |i: usize| points[i].imag as f32;
+        let c_real = _mm_setr_ps(real(0), real(1), real(2), real(3));
+        let c_imag = _mm_setr_ps(imag(0), imag(1), imag(2), imag(3));
+        let escape_value = _mm_set1_ps((escape_radius * escape_radius) as f32);
+        let tolerance_value = _mm_set1_ps((tolerance * tolerance) as f32);
+        let (mut z_real, mut z_imag) = (c_real, c_imag);
+        let (mut saved_real, mut saved_imag) = (z_real, z_imag);
+        let (mut save_iter, mut next_save) = (0, 1u32);
+        let mut active = 0xf;
+
+        for i in 0..max_iterations {
+            let zr2 = _mm_mul_ps(z_real, z_real);
+            let zi2 = _mm_mul_ps(z_imag, z_imag);
+            let zri = _mm_mul_ps(z_real, z_imag);
+            let escaped = _mm_movemask_ps(_mm_cmpgt_ps(_mm_add_ps(zr2, zi2), escape_value)) as u32;
+            active = record_escapes(escaped, active, i, iters);
+
+            if active == 0 {
+                return;
+            }
+            z_real = _mm_add_ps(_mm_sub_ps(zr2, zi2), c_real);
+            z_imag = _mm_add_ps(_mm_add_ps(zri, zri), c_imag);
+
+            if PERIODIC {
+                let d_real = _mm_sub_ps(z_real, saved_real);
+                let d_imag = _mm_sub_ps(z_imag, saved_imag);
+                let dist = _mm_add_ps(_mm_mul_ps(d_real, d_real), _mm_mul_ps(d_imag, d_imag));
+                let returned = _mm_movemask_ps(_mm_cmplt_ps(dist, tolerance_value)) as u32;
+                active = record_periods(returned, active, i + 1 - save_iter, periods);
+
+                if active == 0 {
+                    return;
+                }
+                if i + 1 == next_save {
+                    saved_real = z_real;
+                    saved_imag = z_imag;
+                    save_iter = i + 1;
+                    next_save = next_save.saturating_mul(2);
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use super::super::tests::assert_matches_scalar;
+
+        #[test]
+        fn sse2_matches_scalar() {
+            assert_matches_scalar(escape_iterations_scalar::<f64>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+                for_each_group(points, iterations, Some(periods), |group, iters, periods| unsafe {
+                    match tolerance {
+                        Some(tolerance) => escape_iterations_sse2::<true>(group, max_iterations, escape_radius, tolerance, iters, periods),
+                        None => escape_iterations_sse2::<false>(group, max_iterations, escape_radius, 0.0, iters, periods),
+                    }
+                })
+            });
+        }
+
+        #[test]
+        fn avx_matches_scalar() {
+            if is_x86_feature_detected!("avx") {
+                assert_matches_scalar(escape_iterations_scalar::<f64>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+                    for_each_group(points, iterations, Some(periods), |group, iters, periods| unsafe {
+                        match tolerance {
+                            Some(tolerance) => escape_iterations_avx::<true>(group, max_iterations, escape_radius, tolerance, iters, periods),
+                            None => escape_iterations_avx::<false>(group, max_iterations, escape_radius, 0.0, iters, periods),
+                        }
+                    })
+                });
+            }
+        }
+
+        #[test]
+        fn avx512_matches_scalar() {
+            if is_x86_feature_detected!("avx512f") {
+                assert_matches_scalar(escape_iterations_scalar::<f64>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+                    for_each_group(points, iterations, Some(periods), |group, iters, periods| unsafe {
+                        match tolerance {
+                            Some(tolerance) => escape_iterations_avx512::<true>(group, max_iterations, escape_radius, tolerance, iters, periods),
+                            None => escape_iterations_avx512::<false>(group, max_iterations, escape_radius, 0.0, iters, periods),
+                        }
+                    })
+                });
+            }
+        }
+
+        #[test]
+        fn single_sse2_matches_scalar() {
+            assert_matches_scalar(escape_iterations_scalar::<f32>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+                for_each_group(points, iterations, Some(periods), |group, iters, periods| unsafe {
+                    match tolerance {
+                        Some(tolerance) => escape_iterations_single_sse2::<true>(group, max_iterations, escape_radius, tolerance, iters, periods),
+                        None => escape_iterations_single_sse2::<false>(group, max_iterations, escape_radius, 0.0, iters, periods),
+                    }
+                })
+            });
+        }
+
+        #[test]
+        fn single_avx_matches_scalar() {
+            if is_x86_feature_detected!("avx") {
+                assert_matches_scalar(escape_iterations_scalar::<f32>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+                    for_each_group(points, iterations, Some(periods), |group, iters, periods| unsafe {
+                        match tolerance {
+                            Some(tolerance) => escape_iterations_single_avx::<true>(group, max_iterations, escape_radius, tolerance, iters, periods),
+                            None => escape_iterations_single_avx::<false>(group, max_iterations, escape_radius, 0.0, iters, periods),
+                        }
+                    })
+                });
+            }
+        }
+
+        #[test]
+        fn single_avx512_matches_scalar() {
+            if is_x86_feature_detected!("avx512f") {
+                assert_matches_scalar(escape_iterations_scalar::<f32>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+                    for_each_group(points, iterations, Some(periods), |group, iters, periods| unsafe {
+                        match tolerance {
+                            Some(tolerance) => escape_iterations_single_avx512::<true>(group, max_iterations, escape_radius, tolerance, iters, periods),
+                            None => escape_iterations_single_avx512::<false>(group, max_iterations, escape_radius, 0.0, iters, periods),
+                        }
+                    })
+                });
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod arch {
+    use complex::Complex;
+    use super::escape_iterations_scalar;
+
+    pub fn lanes() -> usize {
+        1
+    }
+
+    pub fn single_lanes() -> usize {
+        1
+    }
+
+    pub fn escape_iterations_row(
+        points: &[Complex],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: Option<f64>,
+        iterations: &mut [u32],
+        periods: Option<&mut [u32]>,
+    ) {
+        escape_iterations_scalar::<f64>(points, max_iterations, escape_radius, tolerance, iterations, periods);
+    }
+
+    pub fn escape_iterations_row_single(
+        points: &[Complex],
+        max_iterations: u32,
+        escape_radius: f64,
+        tolerance: Option<f64>,
+        iterations: &mut [u32],
+        periods: Option<&mut [u32]>,
+    ) {
+        escape_iterations_scalar::<f32>(points, max_iterations, escape_radius, tolerance, iterations, periods);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks a row kernel gives exactly the same results as the scalar kernel `scalar` for rows
+    /// of points crossing the boundary of the set, including a row whose length isn't a multiple
+    /// of the number of lanes. The kernel is checked with and without periodicity checking.
+    pub fn assert_matches_scalar<S, F>(scalar: S, kernel: F)
+        where S: Fn(&[Complex], u32, f64, Option<f64>, &mut [u32], Option<&mut [u32]>),
+              F: Fn(&[Complex], u32, f64, Option<f64>, &mut [u32], &mut [u32]) {
+        for &tolerance in &[None, Some(1e-10)] {
+            for row in 0..40 {
+                let points = (0..203)
+                    .map(|r| Complex::new(-2.0 + r as f64 * 0.0125, -1.0 + row as f64 * 0.05))
+                    .collect::<Vec<_>>();
+                let mut expected = (vec![0; points.len()], vec![0; points.len()]);
+                let mut actual = (vec![0; points.len()], vec![0; points.len()]);
+                scalar(&points, 500, 2.0, tolerance, &mut expected.0, Some(&mut expected.1));
+                kernel(&points, 500, 2.0, tolerance, &mut actual.0, &mut actual.1);
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn row_matches_scalar() {
+        assert_matches_scalar(escape_iterations_scalar::<f64>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+            match tolerance {
+                Some(tolerance) =>
+                    escape_iterations_row_periodic(points, max_iterations, escape_radius, tolerance, iterations, periods),
+                None => escape_iterations_row(points, max_iterations, escape_radius, iterations),
+            }
+        });
+    }
+
+    #[test]
+    fn single_row_matches_scalar() {
+        assert_matches_scalar(escape_iterations_scalar::<f32>, |points, max_iterations, escape_radius, tolerance, iterations, periods| {
+            match tolerance {
+                Some(tolerance) =>
+                    escape_iterations_row_single_periodic(points, max_iterations, escape_radius, tolerance, iterations, periods),
+                None => escape_iterations_row_single(points, max_iterations, escape_radius, iterations),
+            }
+        });
+    }
+}
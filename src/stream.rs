@@ -0,0 +1,176 @@
+use bmp::Image;
+use std::sync::Arc;
+use colour::{self, Colour};
+use fractal::Fractal;
+use {calc_set_parallel, calc_tiles_parallel, reference_orbit, split_rects, Colouring, SetDefinition, Tile, TileCalculator, TileWriter};
+
+/// The colours of the pixels of a set, which are filled in by combining the samples of each
+/// tile as soon as it's calculated.
+///
+/// Only the tiles being calculated have their samples stored, so the memory needed doesn't
+/// depend on the oversampling.
+pub struct PixelData {
+    width_px: u32,
+    height_px: u32,
+    colouring: Arc<Colouring>,
+    /// The colour of each pixel, by row starting from the bottom left.
+    pixels: Vec<Colour>,
+}
+
+impl PixelData {
+    /// Creates pixel data for `def` with every pixel black, which colours the samples using
+    /// `colouring`.
+    pub fn new(def: &SetDefinition, colouring: Arc<Colouring>) -> PixelData {
+        let pixels = vec![colour::BLACK; (def.width_px * def.height_px) as usize];
+        PixelData { width_px: def.width_px, height_px: def.height_px, colouring, pixels }
+    }
+
+    /// Returns the image of the pixels.
+    pub fn image(&self) -> Image {
+        let mut img = Image::new(self.width_px, self.height_px);
+
+        for (x, y) in img.coordinates() {
+            // the image origin is top left
+            let idx = (self.height_px - y - 1) * self.width_px + x;
+            img.set_pixel(x, y, self.pixels[idx as usize].pixel());
+        }
+        img
+    }
+
+    /// Returns the red, green and blue bytes of the pixels by row, starting from the top left
+    /// like an image.
+    pub fn rgb_rows(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 3);
+
+        for row in self.pixels.chunks(self.width_px as usize).rev() {
+            for clr in row {
+                bytes.extend_from_slice(&[clr.r, clr.g, clr.b]);
+            }
+        }
+        bytes
+    }
+}
+
+/// The pixels of a tile, written straight into the rows of the pixels of the whole set.
+pub struct TilePixels<'a> {
+    colouring: &'a Colouring,
+    rows: Vec<&'a mut [Colour]>,
+}
+
+impl TileWriter for PixelData {
+    type Part<'a> = TilePixels<'a>;
+
+    fn split(&mut self, tiles: &[Tile]) -> Vec<TilePixels<'_>> {
+        let rects = tiles.iter()
+            .map(|tile| (tile.x_px as usize, tile.y_px as usize, tile.def.width_px as usize, tile.def.height_px as usize))
+            .collect::<Vec<_>>();
+        let colouring = &*self.colouring;
+        split_rects(&mut self.pixels, self.width_px as usize, &rects)
+            .into_iter()
+            .map(|rows| TilePixels { colouring, rows })
+            .collect()
+    }
+
+    /// Calculates the samples of a tile, then colours them and combines them into the colours
+    /// of its pixels.
+    fn write_tile<F: Fractal>(mut part: TilePixels<'_>, tile: &Tile, calculator: &mut TileCalculator<F>) {
+        let samples = calculator.calc(&tile.def);
+        let colouring = part.colouring;
+        let sample_colour = |idx: usize| colouring.colour(&samples.sample(idx));
+
+        for (y, row) in part.rows.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = colour::pixel_colour(x as u32, y as u32, tile.def.width_px, tile.def.oversampling, &sample_colour);
+            }
+        }
+    }
+}
+
+/// Calculates the set defined by `set_def` for `fractal` in parallel and returns the colours of
+/// its pixels, without storing the samples of the whole set.
+///
+/// The samples are coloured as they are calculated, so the ranges of values the palettes are
+/// spread over are found first from a preview of the set with one sample per pixel. The
+/// colours can differ slightly from the colours rendered from the samples of the whole set.
+/// The samples of the set aren't mirrored in the real axis because the samples on the
+/// calculated side would need to be stored.
+pub fn calc_pixels_parallel<F: Fractal>(set_def: &SetDefinition, fractal: &F, threads: u32, texture: Option<Image>) -> PixelData {
+    let set_def = &set_def.with_resolved_precision();
+    let colouring = preview_colouring(set_def, fractal, threads, texture, 1);
+    let reference = reference_orbit(set_def, fractal);
+    calc_tiles_parallel(set_def, fractal, reference.as_ref(), threads, PixelData::new(set_def, Arc::new(colouring)))
+}
+
+/// Returns the colouring for a set with the ranges of values found from a preview of it, which
+/// has one sample for each square of `scale` by `scale` pixels. The preview isn't shaded, but
+/// the colouring shades the samples like the set.
+pub fn preview_colouring<F: Fractal>(set_def: &SetDefinition, fractal: &F, threads: u32, texture: Option<Image>, scale: u32) -> Colouring {
+    // the preview doesn't need any of the values that aren't used to find the ranges
+    let preview_def = SetDefinition {
+        px_size: set_def.px_size * scale as f64,
+        width_px: set_def.width_px.div_ceil(scale),
+        height_px: set_def.height_px.div_ceil(scale),
+        oversampling: 1,
+        adaptive: None,
+        lighting: None,
+        field_lines: false,
+        ..*set_def
+    };
+    Colouring::new(&calc_set_parallel(&preview_def, fractal, threads), texture).with_shading(set_def)
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exterior::ExteriorColouring;
+    use interior::InteriorColouring;
+    use shading::Lighting;
+    use trap::OrbitTrap;
+    use complex::Complex;
+    use fractal::Mandelbrot;
+    use {calc_set, render};
+
+    #[test]
+    fn same_image_as_samples() {
+        // with the colouring from the samples of the whole set the pixels are the same
+        let def = SetDefinition::new(-2.0, 1.0, -1.0, 0.25, 96, 3, 200, 2.0)
+            .with_interior(InteriorColouring::Distance)
+            .with_lighting(Some(Lighting::parse("45,45").unwrap()))
+            .with_exterior(ExteriorColouring::TrapDistance, OrbitTrap::Point { centre: Complex::new(0.0, 0.0) });
+        let set_data = calc_set(&def, &Mandelbrot, None);
+        let colouring = Arc::new(Colouring::new(&set_data, None));
+        let pixel_data = calc_tiles_parallel(&def, &Mandelbrot, None, 3, PixelData::new(&def, colouring));
+        assert!(pixel_data.image() == render(&set_data, None));
+    }
+
+    #[test]
+    fn preview_colouring() {
+        // the ranges found from the preview are close enough to the ranges of the samples that
+        // most pixels are the same
+        let def = SetDefinition::new(-0.77, -0.76, 0.09, 0.10, 96, 3, 400, 10.0);
+        let pixel_data = calc_pixels_parallel(&def, &Mandelbrot, 3, None);
+        let image = render(&calc_set(&def, &Mandelbrot, None), None);
+        let same = image.coordinates().filter(|&(x, y)| {
+            let idx = (95 - y) * 96 + x;
+            pixel_data.pixels[idx as usize].pixel() == image.get_pixel(x, y)
+        });
+        assert!(same.count() > 96 * 96 * 9 / 10);
+    }
+
+    #[test]
+    fn preview_colouring_shaded() {
+        // the pixels are shaded even though the preview isn't
+        let def = SetDefinition::new(-0.77, -0.76, 0.09, 0.10, 96, 3, 400, 10.0)
+            .with_lighting(Some(Lighting::parse("45,45").unwrap()))
+            .with_field_lines(true);
+        let pixel_data = calc_pixels_parallel(&def, &Mandelbrot, 3, None);
+        let image = render(&calc_set(&def, &Mandelbrot, None), None);
+        let same = image.coordinates().filter(|&(x, y)| {
+            let idx = (95 - y) * 96 + x;
+            pixel_data.pixels[idx as usize].pixel() == image.get_pixel(x, y)
+        });
+        assert!(same.count() > 96 * 96 * 9 / 10);
+    }
+}
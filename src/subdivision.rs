@@ -0,0 +1,265 @@
+/// Rectangles with a side shorter than this many samples are calculated in full rather than
+/// being subdivided any further.
+const MIN_SIZE: usize = 8;
+
+/// How much of the sample grid is calculated.
+///
+/// With subdivision, the samples on the border of a rectangle are calculated first, starting
+/// with the whole grid. If they all have the same escape iterations the rest of the rectangle
+/// is filled with them, otherwise the rectangle is divided into quarters which are handled
+/// in the same way. This relies on the set being connected so that a region of the set or an
+/// iteration band can't be entirely inside a rectangle without touching its border, which
+/// isn't true of the sampled set where filaments are thinner than the samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subdivision {
+    /// Every sample is calculated.
+    Off,
+    /// A rectangle is filled if the samples on its border have the same escape iterations.
+    Fast,
+    /// A rectangle is only filled if the samples on the lines dividing it into quarters have
+    /// the same escape iterations as its border. This makes it much less likely to miss thin
+    /// filaments crossing the rectangle.
+    Conservative,
+}
+
+impl Subdivision {
+    /// Returns the subdivision mode whose name is `name`, or `None` if there isn't one.
+    pub fn from_name(name: &str) -> Option<Subdivision> {
+        match name {
+            "off" => Some(Subdivision::Off),
+            "fast" => Some(Subdivision::Fast),
+            "conservative" => Some(Subdivision::Conservative),
+            _ => None,
+        }
+    }
+}
+
+/// A rectangle of samples, including the samples on its edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rect {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl Rect {
+    /// Returns the coordinates of the samples on the border of the rectangle.
+    fn border(&self) -> Vec<(usize, usize)> {
+        let mut coords = (self.x0..=self.x1).map(|x| (x, self.y0)).collect::<Vec<_>>();
+
+        if self.y1 > self.y0 {
+            coords.extend((self.x0..=self.x1).map(|x| (x, self.y1)));
+        }
+        coords.extend((self.y0 + 1..self.y1).map(|y| (self.x0, y)));
+
+        if self.x1 > self.x0 {
+            coords.extend((self.y0 + 1..self.y1).map(|y| (self.x1, y)));
+        }
+        coords
+    }
+
+    /// Returns the coordinates of the samples on the lines dividing the rectangle into quarters.
+    fn cross(&self) -> Vec<(usize, usize)> {
+        let (xm, ym) = ((self.x0 + self.x1) / 2, (self.y0 + self.y1) / 2);
+        let mut coords = (self.x0 + 1..self.x1).map(|x| (x, ym)).collect::<Vec<_>>();
+        coords.extend((self.y0 + 1..self.y1).filter(|&y| y != ym).map(|y| (xm, y)));
+        coords
+    }
+
+    /// Returns the coordinates of the samples inside the border of the rectangle.
+    fn inside(&self) -> Vec<(usize, usize)> {
+        (self.y0 + 1..self.y1).flat_map(|y| (self.x0 + 1..self.x1).map(move |x| (x, y))).collect()
+    }
+
+    /// Returns the quarters of the rectangle, which share the samples on their common edges.
+    fn quarters(&self) -> [Rect; 4] {
+        let (xm, ym) = ((self.x0 + self.x1) / 2, (self.y0 + self.y1) / 2);
+        [
+            Rect { x0: self.x0, y0: self.y0, x1: xm, y1: ym },
+            Rect { x0: xm, y0: self.y0, x1: self.x1, y1: ym },
+            Rect { x0: self.x0, y0: ym, x1: xm, y1: self.y1 },
+            Rect { x0: xm, y0: ym, x1: self.x1, y1: self.y1 },
+        ]
+    }
+
+    fn is_small(&self) -> bool {
+        self.x1 - self.x0 < MIN_SIZE || self.y1 - self.y0 < MIN_SIZE
+    }
+}
+
+/// The escape iterations and periods of a grid of samples, stored by row.
+struct Grid {
+    width: usize,
+    iterations: Vec<u32>,
+    periods: Vec<u32>,
+    /// Whether each sample has been calculated or filled.
+    done: Vec<bool>,
+    /// The index of the sample each sample was filled from, or its own index if it was
+    /// calculated.
+    sources: Vec<usize>,
+}
+
+impl Grid {
+    /// Calculates the samples at `coords` which haven't already been calculated or filled.
+    fn calc<T, P, F>(&mut self, coords: &[(usize, usize)], point: &P, calc: &mut F)
+        where P: Fn(usize, usize) -> T, F: FnMut(&[T], &mut [u32], &mut [u32]) {
+        let indices = coords.iter()
+            .map(|&(x, y)| y * self.width + x)
+            .filter(|&idx| !self.done[idx])
+            .collect::<Vec<_>>();
+        let points = indices.iter().map(|&idx| point(idx % self.width, idx / self.width)).collect::<Vec<_>>();
+        let mut iterations = vec![0; indices.len()];
+        let mut periods = vec![0; indices.len()];
+        calc(&points, &mut iterations, &mut periods);
+
+        for (&idx, (&iters, &period)) in indices.iter().zip(iterations.iter().zip(&periods)) {
+            self.iterations[idx] = iters;
+            self.periods[idx] = period;
+            self.done[idx] = true;
+        }
+    }
+
+    /// Returns the escape iterations shared by all the samples at `coords`, or `None` if they
+    /// aren't all the same.
+    fn uniform(&self, coords: &[(usize, usize)]) -> Option<u32> {
+        let mut values = coords.iter().map(|&(x, y)| self.iterations[y * self.width + x]);
+        let first = values.next()?;
+
+        if values.all(|iters| iters == first) { Some(first) } else { None }
+    }
+
+    /// Fills the samples inside the border of `rect` with `iterations`, recording the bottom
+    /// left corner of the rectangle as their source. Their orbits haven't been checked for
+    /// cycles, so the filled samples are given the period of the corner like the other values
+    /// copied from it.
+    fn fill(&mut self, rect: &Rect, iterations: u32) {
+        let source = rect.y0 * self.width + rect.x0;
+        let period = self.periods[source];
+
+        for (x, y) in rect.inside() {
+            let idx = y * self.width + x;
+
+            if !self.done[idx] {
+                self.iterations[idx] = iterations;
+                self.periods[idx] = period;
+                self.done[idx] = true;
+                self.sources[idx] = source;
+            }
+        }
+    }
+}
+
+/// Returns the escape iterations and periods of a grid of `width` by `height` samples, stored
+/// by row, along with the index of the sample each sample was filled from. The source of a
+/// calculated sample is its own index, and the source of a filled sample is a calculated
+/// sample with a lower index, so the other values of filled samples can be copied from their
+/// sources instead of being calculated.
+///
+/// `point` gives the point of the sample at a column and row of the grid. `calc` calculates
+/// the escape iterations and periods of a batch of points, and is only used for the samples
+/// that subdivision can't fill. When subdivision is off it is called with one row at a time.
+pub fn subdivide<T, P, F>(width: usize, height: usize, mode: Subdivision, point: P, mut calc: F) -> (Vec<u32>, Vec<u32>, Vec<usize>)
+    where P: Fn(usize, usize) -> T, F: FnMut(&[T], &mut [u32], &mut [u32]) {
+    let mut grid = Grid {
+        width,
+        iterations: vec![0; width * height],
+        periods: vec![0; width * height],
+        done: vec![false; width * height],
+        sources: (0..width * height).collect(),
+    };
+    if width == 0 || height == 0 {
+        return (grid.iterations, grid.periods, grid.sources);
+    }
+    if mode == Subdivision::Off {
+        for y in 0..height {
+            grid.calc(&(0..width).map(|x| (x, y)).collect::<Vec<_>>(), &point, &mut calc);
+        }
+        return (grid.iterations, grid.periods, grid.sources);
+    }
+    let mut rects = vec![Rect { x0: 0, y0: 0, x1: width - 1, y1: height - 1 }];
+
+    while let Some(rect) = rects.pop() {
+        let mut checked = rect.border();
+        grid.calc(&checked, &point, &mut calc);
+        let mut uniform = grid.uniform(&checked);
+
+        if uniform.is_some() && mode == Subdivision::Conservative {
+            let cross = rect.cross();
+            grid.calc(&cross, &point, &mut calc);
+            checked.extend(cross);
+            uniform = grid.uniform(&checked);
+        }
+        match uniform {
+            Some(iterations) => grid.fill(&rect, iterations),
+            None if rect.is_small() => grid.calc(&rect.inside(), &point, &mut calc),
+            None => rects.extend_from_slice(&rect.quarters()),
+        }
+    }
+    (grid.iterations, grid.periods, grid.sources)
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Subdivides a grid of samples whose escape iterations are given by `iterations` and
+    /// returns the iterations along with the number of samples calculated.
+    fn subdivide_grid<I>(size: usize, mode: Subdivision, iterations: I) -> (Vec<u32>, usize)
+        where I: Fn(usize, usize) -> u32 {
+        let mut count = 0;
+        let (iters, _, sources) = subdivide(size, size, mode, |x, y| (x, y), |points, iters, _| {
+            count += points.len();
+
+            for (point, iters) in points.iter().zip(iters.iter_mut()) {
+                *iters = iterations(point.0, point.1);
+            }
+        });
+        // the filled samples have the iterations of their sources, which were calculated
+        for (idx, &source) in sources.iter().enumerate() {
+            assert!(source <= idx && sources[source] == source && iters[source] == iters[idx]);
+        }
+        (iters, count)
+    }
+
+    fn expected<I>(size: usize, iterations: I) -> Vec<u32> where I: Fn(usize, usize) -> u32 {
+        (0..size * size).map(|idx| iterations(idx % size, idx / size)).collect()
+    }
+
+    #[test]
+    fn off_calculates_every_sample() {
+        let disc = |x: usize, y: usize| if (x * x + y * y) < 900 { 0 } else { 5 };
+        assert_eq!(subdivide_grid(50, Subdivision::Off, disc), (expected(50, disc), 2500));
+    }
+
+    #[test]
+    fn fast_fills_uniform_regions() {
+        // a region touching the border of the grid, like the regions of the set
+        let disc = |x: usize, y: usize| if (x as i64 - 60).pow(2) + (y as i64).pow(2) < 900 { 0 } else { 5 };
+        let (iterations, count) = subdivide_grid(101, Subdivision::Fast, disc);
+        assert_eq!(iterations, expected(101, disc));
+        assert!(count < 101 * 101 / 2, "{} samples calculated", count);
+    }
+
+    #[test]
+    fn conservative_finds_filament() {
+        // a thin line which doesn't touch the border of the grid but crosses the middle row
+        let filament = |x: usize, y: usize| if x == 20 && (30..35).contains(&y) { 0 } else { 5 };
+        let (fast, _) = subdivide_grid(65, Subdivision::Fast, filament);
+        assert!(fast.iter().all(|&iters| iters == 5));
+        let (conservative, _) = subdivide_grid(65, Subdivision::Conservative, filament);
+        assert_eq!(conservative, expected(65, filament));
+    }
+
+    #[test]
+    fn border() {
+        let rect = Rect { x0: 1, y0: 2, x1: 3, y1: 4 };
+        let mut border = rect.border();
+        border.sort();
+        assert_eq!(border, vec![(1, 2), (1, 3), (1, 4), (2, 2), (2, 4), (3, 2), (3, 3), (3, 4)]);
+        assert_eq!(rect.inside(), vec![(2, 3)]);
+        assert_eq!(Rect { x0: 1, y0: 2, x1: 1, y1: 2 }.border(), vec![(1, 2)]);
+    }
+}
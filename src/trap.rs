@@ -0,0 +1,211 @@
+use complex::Complex;
+use fractal::Fractal;
+
+/// A geometric shape in the complex plane used for orbit trap colouring.
+///
+/// As a point is iterated its orbit is tested against the trap and the closest approach of
+/// the orbit to the trap is used to colour the point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrbitTrap {
+    Point { centre: Complex },
+    /// An infinite line through `point` at `angle` radians to the real axis.
+    Line { point: Complex, angle: f64 },
+    /// A horizontal and a vertical line crossing at `centre`.
+    Cross { centre: Complex },
+    Circle { centre: Complex, radius: f64 },
+    /// A rectangle with its bottom-left corner at `corner`. This is the only trap which traps
+    /// orbit points for texture mapping an image.
+    Rectangle { corner: Complex, size: Complex },
+}
+
+/// The result of testing an orbit against a trap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trapped {
+    pub iterations: u32,
+    /// The vector from the closest point on the trap to the closest point of the orbit.
+    pub closest: Complex,
+    /// The texture coordinates of the first orbit point inside a rectangle trap, each in the
+    /// range 0 to 1, or `None` if the orbit never entered the rectangle.
+    pub texture_coords: Option<Complex>,
+}
+
+impl OrbitTrap {
+    /// Parses a trap from a string containing its shape and parameters separated by commas:
+    ///
+    /// * `point:<real>,<imag>`
+    /// * `line:<real>,<imag>,<angle in degrees>`
+    /// * `cross:<real>,<imag>`
+    /// * `circle:<real>,<imag>,<radius>`
+    /// * `rectangle:<real>,<imag>,<width>,<height>`
+    pub fn parse(value: &str) -> Option<OrbitTrap> {
+        let mut parts = value.splitn(2, ':');
+        let shape = parts.next()?;
+        let params = parts.next()?.split(',').map(|param| param.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>().ok()?;
+
+        match (shape, params.as_slice()) {
+            ("point", &[real, imag]) =>
+                Some(OrbitTrap::Point { centre: Complex::new(real, imag) }),
+            ("line", &[real, imag, angle]) =>
+                Some(OrbitTrap::Line { point: Complex::new(real, imag), angle: angle.to_radians() }),
+            ("cross", &[real, imag]) =>
+                Some(OrbitTrap::Cross { centre: Complex::new(real, imag) }),
+            ("circle", &[real, imag, radius]) =>
+                Some(OrbitTrap::Circle { centre: Complex::new(real, imag), radius }),
+            ("rectangle", &[real, imag, width, height]) =>
+                Some(OrbitTrap::Rectangle { corner: Complex::new(real, imag), size: Complex::new(width, height) }),
+            _ => None,
+        }
+    }
+
+    /// Returns the vector from the closest point on the trap to `z`.
+    pub fn offset(&self, z: Complex) -> Complex {
+        match *self {
+            OrbitTrap::Point { centre } => z - centre,
+            OrbitTrap::Line { point, angle } => {
+                // remove the component of the offset along the line
+                let dir = Complex::from_polar(1.0, angle);
+                let rel = z - point;
+                let along = rel.real * dir.real + rel.imag * dir.imag;
+                rel - Complex::new(dir.real * along, dir.imag * along)
+            }
+            OrbitTrap::Cross { centre } => {
+                let rel = z - centre;
+
+                if rel.real.abs() < rel.imag.abs() {
+                    Complex::new(rel.real, 0.0)
+                } else {
+                    Complex::new(0.0, rel.imag)
+                }
+            }
+            OrbitTrap::Circle { centre, radius } => {
+                let rel = z - centre;
+                let norm = rel.abs();
+
+                if norm == 0.0 {
+                    Complex::new(radius, 0.0)
+                } else {
+                    let scale = (norm - radius) / norm;
+                    Complex::new(rel.real * scale, rel.imag * scale)
+                }
+            }
+            OrbitTrap::Rectangle { corner, size } => {
+                let rel = z - corner;
+                let clamp = |val: f64, max: f64| val - val.clamp(0.0, max);
+                Complex::new(clamp(rel.real, size.real), clamp(rel.imag, size.imag))
+            }
+        }
+    }
+
+    /// Returns the texture coordinates of `z` if it is inside a rectangle trap.
+    fn texture_coords(&self, z: Complex) -> Option<Complex> {
+        match *self {
+            OrbitTrap::Rectangle { corner, size } => {
+                let u = (z.real - corner.real) / size.real;
+                let v = (z.imag - corner.imag) / size.imag;
+
+                if (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v) {
+                    Some(Complex::new(u, v))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Iterates `point` of `fractal` until it escapes, tracking the closest approach of its orbit
+    /// to the trap.
+    pub fn trap_orbit<F: Fractal>(&self, fractal: &F, point: Complex, max_iterations: u32, escape_radius: f64) -> Trapped {
+        let mut z = fractal.init(point);
+        let mut closest = self.offset(z);
+        // the point itself isn't trapped, otherwise the texture would just be drawn over the
+        // rectangle it is mapped to
+        let mut texture_coords = None;
+
+        for i in 0..max_iterations {
+            if fractal.bailout(z, escape_radius) {
+                return Trapped { iterations: i, closest, texture_coords };
+            }
+            z = fractal.step(z, point);
+            let offset = self.offset(z);
+
+            if offset.norm_sqr() < closest.norm_sqr() {
+                closest = offset;
+            }
+            if texture_coords.is_none() {
+                texture_coords = self.texture_coords(z);
+            }
+        }
+        Trapped { iterations: 0, closest, texture_coords }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fractal::Mandelbrot;
+
+    #[test]
+    fn parse() {
+        assert_eq!(OrbitTrap::parse("point:1,2"), Some(OrbitTrap::Point { centre: Complex::new(1.0, 2.0) }));
+        assert_eq!(OrbitTrap::parse("circle:0,0,0.5"), Some(OrbitTrap::Circle { centre: Complex::new(0.0, 0.0), radius: 0.5 }));
+        assert_eq!(OrbitTrap::parse("line:0,0,90").map(|trap| trap.offset(Complex::new(2.0, 3.0)).real.round()), Some(2.0));
+        assert_eq!(OrbitTrap::parse("circle:0,0"), None);
+        assert_eq!(OrbitTrap::parse("square:0,0"), None);
+        assert_eq!(OrbitTrap::parse("point"), None);
+    }
+
+    #[test]
+    fn offset_point() {
+        let trap = OrbitTrap::Point { centre: Complex::new(1.0, 1.0) };
+        assert_eq!(trap.offset(Complex::new(4.0, 5.0)), Complex::new(3.0, 4.0));
+    }
+
+    #[test]
+    fn offset_line() {
+        let trap = OrbitTrap::Line { point: Complex::new(0.0, 1.0), angle: 0.0 };
+        assert_eq!(trap.offset(Complex::new(4.0, 3.0)), Complex::new(0.0, 2.0));
+    }
+
+    #[test]
+    fn offset_cross() {
+        let trap = OrbitTrap::Cross { centre: Complex::new(0.0, 0.0) };
+        assert_eq!(trap.offset(Complex::new(4.0, -1.0)), Complex::new(0.0, -1.0));
+        assert_eq!(trap.offset(Complex::new(0.5, -1.0)), Complex::new(0.5, 0.0));
+    }
+
+    #[test]
+    fn offset_circle() {
+        let trap = OrbitTrap::Circle { centre: Complex::new(1.0, 0.0), radius: 1.0 };
+        assert_eq!(trap.offset(Complex::new(4.0, 0.0)), Complex::new(2.0, 0.0));
+        assert_eq!(trap.offset(Complex::new(1.0, 0.5)), Complex::new(0.0, -0.5));
+    }
+
+    #[test]
+    fn offset_rectangle() {
+        let trap = OrbitTrap::Rectangle { corner: Complex::new(0.0, 0.0), size: Complex::new(2.0, 1.0) };
+        assert_eq!(trap.offset(Complex::new(1.0, 0.5)), Complex::new(0.0, 0.0));
+        assert_eq!(trap.offset(Complex::new(3.0, -1.0)), Complex::new(1.0, -1.0));
+    }
+
+    #[test]
+    fn trap_orbit() {
+        // the orbit of 0.25 + 0i starts at 0.25 and approaches 0.5 from below
+        let trap = OrbitTrap::Point { centre: Complex::new(0.5, 0.0) };
+        let trapped = trap.trap_orbit(&Mandelbrot, Complex::new(0.25, 0.0), 100, 2.0);
+        assert_eq!(trapped.iterations, 0);
+        assert!(trapped.closest.abs() < 0.02);
+        assert_eq!(trapped.texture_coords, None);
+    }
+
+    #[test]
+    fn trap_orbit_texture_coords() {
+        // the orbit of 1 + 0i is 1, 2, 5, ...
+        let trap = OrbitTrap::Rectangle { corner: Complex::new(1.5, -0.5), size: Complex::new(1.0, 1.0) };
+        let trapped = trap.trap_orbit(&Mandelbrot, Complex::new(1.0, 0.0), 100, 2.0);
+        assert_eq!(trapped.iterations, 2);
+        assert_eq!(trapped.texture_coords, Some(Complex::new(0.5, 0.5)));
+    }
+}
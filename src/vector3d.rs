@@ -3,54 +3,70 @@ use std::ops::Sub;
 use std::ops::Div;
 use std::ops::Mul;
 use colour::Colour;
+use precision::Float;
 
-// TODO are there type bounds so this could be defined for all numbers or all floating point numbers?
+/// A vector whose components are `T`, which is f64 unless another float type is given.
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Vector3d {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+pub struct Vector3d<T = f64> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
 }
 
-impl Vector3d {
-    pub fn new(x: f64, y: f64, z: f64) -> Vector3d {
+impl<T: Float> Vector3d<T> {
+    pub fn new(x: T, y: T, z: T) -> Vector3d<T> {
         Vector3d { x, y, z }
     }
 
+    pub fn dot(&self, other: &Vector3d<T>) -> T {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn magnitude(&self) -> T {
+        self.dot(self).sqrt()
+    }
+
+    /// Returns a vector with the same direction as this vector and a magnitude of one.
+    pub fn normalise(&self) -> Vector3d<T> {
+        *self / self.magnitude()
+    }
+}
+
+impl Vector3d {
     pub fn from_colour(col: &Colour) -> Vector3d {
         Vector3d::new(col.r as f64, col.g as f64, col.b as f64)
     }
 }
 
-impl Add for Vector3d {
-    type Output = Vector3d;
+impl<T: Float> Add for Vector3d<T> {
+    type Output = Vector3d<T>;
 
-    fn add(self, other: Vector3d) -> Vector3d {
+    fn add(self, other: Vector3d<T>) -> Vector3d<T> {
         Vector3d { x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
     }
 }
 
-impl Sub for Vector3d {
-    type Output = Vector3d;
+impl<T: Float> Sub for Vector3d<T> {
+    type Output = Vector3d<T>;
 
-    fn sub(self, other: Vector3d) -> Vector3d {
+    fn sub(self, other: Vector3d<T>) -> Vector3d<T> {
         Vector3d { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
     }
 }
 
-impl Div<f64> for Vector3d {
-    type Output = Vector3d;
+impl<T: Float> Div<T> for Vector3d<T> {
+    type Output = Vector3d<T>;
 
-    fn div(self, divisor: f64) -> Vector3d {
+    fn div(self, divisor: T) -> Vector3d<T> {
         Vector3d { x: self.x / divisor, y: self.y / divisor, z: self.z / divisor }
     }
 }
 
 
-impl Mul<f64> for Vector3d {
-    type Output = Vector3d;
+impl<T: Float> Mul<T> for Vector3d<T> {
+    type Output = Vector3d<T>;
 
-    fn mul(self, multiplier: f64) -> Vector3d {
+    fn mul(self, multiplier: T) -> Vector3d<T> {
         Vector3d { x: self.x * multiplier, y: self.y * multiplier, z: self.z * multiplier }
     }
 }
@@ -80,6 +96,27 @@ mod tests {
         assert_eq!(v3d1 - v3d2, Vector3d::new(2.0, 4.0, 6.0));
     }
 
+    #[test]
+    fn dot() {
+        let v3d1 = Vector3d::new(3.0, 6.0, 9.0);
+        let v3d2 = Vector3d::new(1.0, 2.0, 3.0);
+        assert_eq!(v3d1.dot(&v3d2), 42.0);
+    }
+
+    #[test]
+    fn normalise() {
+        let v3d = Vector3d::new(2.0, 4.0, 4.0);
+        assert_eq!(v3d.magnitude(), 6.0);
+        assert_eq!(v3d.normalise(), Vector3d::new(1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0));
+    }
+
+    #[test]
+    fn single_precision() {
+        let v3d = Vector3d::new(2.0f32, 4.0, 4.0);
+        assert_eq!(v3d.magnitude(), 6.0f32);
+        assert_eq!(v3d * 0.5, Vector3d::new(1.0f32, 2.0, 2.0));
+    }
+
     #[test]
     fn add() {
         let v3d1 = Vector3d::new(3.0, 6.0, 9.0);